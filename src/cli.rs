@@ -0,0 +1,209 @@
+use std::path::{Path, PathBuf};
+
+use crate::OpenOptions;
+
+// Command-line options: short name, long name, value name and description
+#[rustfmt::skip]
+const OPTIONS: &[(&str, &str, &str, &str)] = &[
+    ("-c", "--config", "DIR", "Look for configuration files in DIR first"),
+    ("-R", "--readonly", "", "Open the files in read-only mode"),
+    ("-s", "--syntax", "NAME", "Highlight the files with the syntax NAME"),
+    ("", "--hex", "", "Open the files in the hex view"),
+    ("", "--recover", "", "Recover unsaved changes from the swap files"),
+    ("", "--check-config", "", "Report the problems of the configuration files and exit"),
+    ("-V", "--version", "", "Show the version"),
+    ("-h", "--help", "", "Show this help message"),
+];
+
+#[derive(Default, Debug)]
+pub struct Cli {
+    pub help: bool,
+    pub version: bool,
+    pub check_config: bool,
+    pub config_dir: Option<PathBuf>,
+    // Options applying to the files opened without any
+    pub options: OpenOptions,
+    // Files to open one after another
+    pub files: Vec<(String, OpenOptions)>,
+}
+
+impl Cli {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut cli = Self::default();
+        let mut targets = Vec::new();
+        let mut line = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                targets.extend(args.by_ref().map(|arg| (arg, line.take())));
+            } else if let Some(n) = arg.strip_prefix('+') {
+                line = Some(parse_number(n)?);
+            } else if arg.starts_with('-') && arg != "-" {
+                let (name, value) = match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (arg.as_str(), None),
+                };
+                let Some(&(_, long, value_name, _)) = OPTIONS
+                    .iter()
+                    .find(|(short, long, ..)| name == *short || name == *long)
+                else {
+                    return Err(format!("Unknown option {name}. Run rabi --help for usage."));
+                };
+                let value = match (value_name.is_empty(), value) {
+                    (true, None) => String::new(),
+                    (true, Some(_)) => return Err(format!("Option {long} takes no value")),
+                    (false, Some(value)) => value,
+                    (false, None) => args
+                        .next()
+                        .ok_or_else(|| format!("Option {long} needs a value {value_name}"))?,
+                };
+                match long {
+                    "--config" => cli.config_dir = Some(PathBuf::from(value)),
+                    "--readonly" => cli.options.readonly = true,
+                    "--syntax" => cli.options.syntax = Some(value),
+                    "--hex" => cli.options.hex = true,
+                    "--recover" => cli.options.recover = true,
+                    "--version" => cli.version = true,
+                    "--check-config" => cli.check_config = true,
+                    _ => cli.help = true,
+                }
+            } else {
+                targets.push((arg, line.take()));
+            }
+        }
+        if line.is_some() {
+            return Err("+LINE must be followed by a file name".to_string());
+        }
+        for (target, line) in targets {
+            let (file, target_line, col) = split_target(&target);
+            let options = OpenOptions {
+                line: line.or(target_line),
+                col: col.filter(|_| line.is_none()),
+                ..cli.options.clone()
+            };
+            cli.files.push((file, options));
+        }
+        Ok(cli)
+    }
+
+    pub fn help() -> String {
+        let mut help = String::from(
+            "Rabi - A simple text editor.\n\n\
+            Usage: rabi [OPTIONS] [[+LINE] FILE[:LINE[:COL]]]...\n\n\
+            Files are opened one after another. Without files, a new file is created.\n\n\
+            Options:\n",
+        );
+        let mut lines: Vec<(String, &str)> = OPTIONS
+            .iter()
+            .map(|(short, long, value, description)| match *short {
+                "" => (format!("    {long} {value}"), *description),
+                _ => (format!("{short}, {long} {value}"), *description),
+            })
+            .collect();
+        lines.push((
+            "    --".to_string(),
+            "Treat the following arguments as file names",
+        ));
+        let width = lines
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or_default();
+        for (name, description) in lines {
+            help.push_str(&format!("  {name:width$}  {description}\n"));
+        }
+        help
+    }
+}
+
+fn parse_number(s: &str) -> Result<usize, String> {
+    s.parse().map_err(|_| format!("Invalid line number: {s}"))
+}
+
+// Split `file:line:col` targets, as printed by compilers, unless the whole name is an existing file
+fn split_target(target: &str) -> (String, Option<usize>, Option<usize>) {
+    if Path::new(target).exists() {
+        return (target.to_string(), None, None);
+    }
+    let trimmed = target.strip_suffix(':').unwrap_or(target);
+    let mut parts = trimmed.rsplitn(3, ':');
+    let numbers: Vec<Option<usize>> = parts.by_ref().take(2).map(|p| p.parse().ok()).collect();
+    match (numbers.as_slice(), parts.next()) {
+        ([Some(col), Some(line)], Some(file)) if !file.is_empty() => {
+            (file.to_string(), Some(*line), Some(*col))
+        }
+        _ => match trimmed.rsplit_once(':') {
+            Some((file, line)) if !file.is_empty() => match line.parse() {
+                Ok(line) => (file.to_string(), Some(line), None),
+                Err(_) => (target.to_string(), None, None),
+            },
+            _ => (target.to_string(), None, None),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    // File, line and column of each file to open
+    fn targets(args: &[&str]) -> Vec<(String, Option<usize>, Option<usize>)> {
+        let cli = parse(args).unwrap();
+        (cli.files.into_iter())
+            .map(|(file, options)| (file, options.line, options.col))
+            .collect()
+    }
+
+    #[test]
+    fn split_targets() {
+        let none = |f: &str| (f.to_string(), None, None);
+        assert_eq!(split_target("a.rs"), none("a.rs"));
+        assert_eq!(split_target("a.rs:3"), ("a.rs".to_string(), Some(3), None));
+        assert_eq!(
+            split_target("a.rs:3:7:"),
+            ("a.rs".to_string(), Some(3), Some(7))
+        );
+        assert_eq!(
+            split_target("c:/a.rs:3"),
+            ("c:/a.rs".to_string(), Some(3), None)
+        );
+        assert_eq!(split_target("a.rs:x"), none("a.rs:x"));
+        assert_eq!(split_target(":3"), none(":3"));
+    }
+
+    #[test]
+    fn line_options() {
+        assert_eq!(
+            targets(&["+5", "a.rs:3:7", "b.rs:2"]),
+            [
+                ("a.rs".to_string(), Some(5), None),
+                ("b.rs".to_string(), Some(2), None)
+            ]
+        );
+        assert_eq!(
+            targets(&["--", "-R", "+2"]),
+            [
+                ("-R".to_string(), None, None),
+                ("+2".to_string(), None, None)
+            ]
+        );
+        assert!(parse(&["a.rs", "+5"]).is_err());
+        assert!(parse(&["+x", "a.rs"]).is_err());
+    }
+
+    #[test]
+    fn options() {
+        let cli = parse(&["-R", "--syntax=rust", "-c", "dir", "a"]).unwrap();
+        assert!(cli.files[0].1.readonly);
+        assert_eq!(cli.files[0].1.syntax.as_deref(), Some("rust"));
+        assert_eq!(cli.config_dir, Some(PathBuf::from("dir")));
+        assert!(parse(&["--hex=yes"]).is_err());
+        assert!(parse(&["--syntax"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        assert!(parse(&["-"]).is_ok_and(|cli| cli.files[0].0 == "-"));
+    }
+}
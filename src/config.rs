@@ -1,79 +1,347 @@
 use std::{
-    fmt::Display,
-    fs::File,
-    io::{BufRead, BufReader},
-    path::{Path, PathBuf},
-    str::FromStr,
+    collections::BTreeMap, env, fmt::Display, fs, path::PathBuf, str::FromStr, time::SystemTime,
 };
 
+use crate::{check_themes, file_stamp, ColorDepth, KeyMap, SyntaxConfig, Theme};
+
+// The shipped configuration files, used when no directory has a file of the same name
+const DEFAULT_FILES: &[(&str, &str)] = &[
+    ("bash.ini", include_str!("../config/bash.ini")),
+    ("c.ini", include_str!("../config/c.ini")),
+    ("cmake.ini", include_str!("../config/cmake.ini")),
+    ("cpp.ini", include_str!("../config/cpp.ini")),
+    ("csharp.ini", include_str!("../config/csharp.ini")),
+    ("css.ini", include_str!("../config/css.ini")),
+    ("dart.ini", include_str!("../config/dart.ini")),
+    ("dockerfile.ini", include_str!("../config/dockerfile.ini")),
+    ("elixir.ini", include_str!("../config/elixir.ini")),
+    ("fish.ini", include_str!("../config/fish.ini")),
+    ("gitignore.ini", include_str!("../config/gitignore.ini")),
+    ("go.ini", include_str!("../config/go.ini")),
+    ("haskell.ini", include_str!("../config/haskell.ini")),
+    ("html.ini", include_str!("../config/html.ini")),
+    ("ini.ini", include_str!("../config/ini.ini")),
+    ("java.ini", include_str!("../config/java.ini")),
+    ("javascript.ini", include_str!("../config/javascript.ini")),
+    ("json.ini", include_str!("../config/json.ini")),
+    ("keys.ini", include_str!("../config/keys.ini")),
+    ("kotlin.ini", include_str!("../config/kotlin.ini")),
+    ("lua.ini", include_str!("../config/lua.ini")),
+    ("makefile.ini", include_str!("../config/makefile.ini")),
+    ("markdown.ini", include_str!("../config/markdown.ini")),
+    ("nim.ini", include_str!("../config/nim.ini")),
+    ("nosql.ini", include_str!("../config/nosql.ini")),
+    ("perl.ini", include_str!("../config/perl.ini")),
+    ("php.ini", include_str!("../config/php.ini")),
+    ("powershell.ini", include_str!("../config/powershell.ini")),
+    ("python.ini", include_str!("../config/python.ini")),
+    ("r.ini", include_str!("../config/r.ini")),
+    ("rabi.ini", include_str!("../config/rabi.ini")),
+    ("racket.ini", include_str!("../config/racket.ini")),
+    ("ruby.ini", include_str!("../config/ruby.ini")),
+    ("rust.ini", include_str!("../config/rust.ini")),
+    ("scala.ini", include_str!("../config/scala.ini")),
+    ("sql.ini", include_str!("../config/sql.ini")),
+    ("swift.ini", include_str!("../config/swift.ini")),
+    ("toml.ini", include_str!("../config/toml.ini")),
+    ("typescript.ini", include_str!("../config/typescript.ini")),
+    ("v.ini", include_str!("../config/v.ini")),
+    ("xml.ini", include_str!("../config/xml.ini")),
+    ("yaml.ini", include_str!("../config/yaml.ini")),
+    ("zsh.ini", include_str!("../config/zsh.ini")),
+];
+
 // Configuration for rabi
-#[derive(Default, Debug)]
+#[derive(Default, Clone, Debug)]
 pub struct Config {
     // The size of tab, default is 4
     pub tab_stop: usize,
 
-    pub quit_times: usize,
+    // Whether Tab inserts spaces instead of a tab character
+    pub expand_tab: bool,
+
+    // The number of columns of an indentation level
+    pub indent_size: usize,
 
     // The duration of shown message, in seconds
     pub message_duration: usize,
 
+    // The interval between swap file writes, in seconds, 0 to disable swap files
+    pub swap_interval: usize,
+
     // Whether to show line numbers
     pub show_line_numbers: bool,
 
-    pub config_folder: PathBuf,
+    // Whether to wrap long lines at the window width instead of scrolling horizontally
+    pub soft_wrap: bool,
+
+    // The width that paragraphs are reflowed to
+    pub text_width: usize,
+
+    // Whether to end saved files with a newline even if the original file did not
+    pub insert_final_newline: bool,
+
+    // Key bindings from `keys.ini`
+    pub keys: KeyMap,
+
+    // Colors of the highlighting and of the interface
+    pub theme: Theme,
+
+    // Directories searched for configuration files, by decreasing precedence
+    pub config_dirs: Vec<PathBuf>,
 }
 
 impl Config {
-    pub fn load(config_folder: PathBuf) -> Result<Self, String> {
+    // Load the embedded `rabi.ini`, overridden by the keys of the first one found in `config_dirs`
+    pub fn load(config_dirs: Vec<PathBuf>) -> Result<Self, String> {
+        Self::check(config_dirs).map_err(|errors| errors.join("\n"))
+    }
+
+    // Like `load`, reporting every problem of the files, including `keys.ini` and the theme
+    pub fn check(config_dirs: Vec<PathBuf>) -> Result<Self, Vec<String>> {
         let mut config = Config {
             tab_stop: 4,
-            quit_times: 2,
+            expand_tab: false,
+            indent_size: 4,
             message_duration: 5,
+            swap_interval: 4,
             show_line_numbers: true,
-            config_folder: config_folder.clone(),
+            soft_wrap: false,
+            text_width: 80,
+            insert_final_newline: false,
+            keys: KeyMap::default(),
+            theme: Theme::default(),
+            config_dirs: Vec::new(),
         };
-        parse_ini_file(
-            config_folder.join("rabi.ini").as_path(),
-            &mut |key, value| {
+        let mut theme = String::new();
+        let mut colors = ColorDepth::detect();
+        let mut errors = Vec::new();
+        for (location, contents) in config_layers(&config_dirs, "rabi.ini")? {
+            let result = parse_ini(&location, &contents, &mut |key, value| {
                 match key {
                     "tab_stop" => match parse_value(value)? {
                         0 => return Err("tab_stop must be greater than 0".to_string()),
                         v => config.tab_stop = v,
                     },
-                    "quit_times" => match parse_value(value)? {
-                        0 => return Err("quit_times must be greater than 0".to_string()),
-                        v => config.quit_times = v,
+                    "expand_tab" => config.expand_tab = parse_value(value)?,
+                    "indent_size" => match parse_value(value)? {
+                        0 => return Err("indent_size must be greater than 0".to_string()),
+                        v => config.indent_size = v,
                     },
                     "message_duration" => config.message_duration = parse_value(value)?,
+                    "swap_interval" => config.swap_interval = parse_value(value)?,
                     "show_line_numbers" => config.show_line_numbers = parse_value(value)?,
-                    _ => return Err("Unknown key in configuration file: {key}".to_string()),
+                    "soft_wrap" => config.soft_wrap = parse_value(value)?,
+                    "text_width" => match parse_value(value)? {
+                        0 => return Err("text_width must be greater than 0".to_string()),
+                        v => config.text_width = v,
+                    },
+                    "insert_final_newline" => config.insert_final_newline = parse_value(value)?,
+                    "theme" => theme = parse_value(value)?,
+                    "colors" => colors = value.trim().parse()?,
+                    // Deprecated: quitting asks for confirmation instead of repeated presses
+                    "quit_times" => (),
+                    _ => return Err(format!("Unknown key: {key}")),
                 }
                 Ok(())
-            },
-        )?;
+            });
+            errors.extend(result.err().unwrap_or_default());
+        }
+        match KeyMap::load(&config_dirs) {
+            Ok(keys) => config.keys = keys,
+            Err(e) => errors.extend(e),
+        }
+        match Theme::load(&theme, &config_dirs) {
+            Ok(mut theme) => {
+                theme.downgrade(colors);
+                config.theme = theme;
+            }
+            Err(e) => errors.extend(e),
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        config.config_dirs = config_dirs;
         Ok(config)
     }
 }
 
-pub fn parse_ini_file(
-    path: &Path,
+fn default_file(name: &str) -> &'static str {
+    DEFAULT_FILES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map_or("", |(_, contents)| contents)
+}
+
+// Whether a configuration file describes a syntax, as opposed to the editor settings
+pub fn is_syntax_file(name: &str) -> bool {
+    !matches!(name, "rabi.ini" | "keys.ini")
+}
+
+// The location and contents of the embedded file `name`, then of the first one found in
+// `config_dirs`
+pub fn config_layers(
+    config_dirs: &[PathBuf],
+    name: &str,
+) -> Result<Vec<(String, String)>, Vec<String>> {
+    let mut layers = vec![(format!("built-in {name}"), default_file(name).to_string())];
+    if let Some(path) = config_dirs
+        .iter()
+        .map(|dir| dir.join(name))
+        .find(|p| p.is_file())
+    {
+        let contents =
+            fs::read_to_string(&path).map_err(|e| vec![format!("{}: {e}", path.display())])?;
+        layers.push((path.display().to_string(), contents));
+    }
+    Ok(layers)
+}
+
+// `$RABI_CONFIG_DIR`, `$XDG_CONFIG_HOME/rabi`, `~/.config/rabi`, then the `config` folder next to
+// the executable
+pub fn config_dirs() -> Vec<PathBuf> {
+    let env_dir = |var| {
+        env::var_os(var)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    let home = env_dir("HOME").or_else(|| env_dir("USERPROFILE"));
+    let exe = env::current_exe().ok();
+    let mut dirs = Vec::new();
+    for dir in [
+        env_dir("RABI_CONFIG_DIR"),
+        env_dir("XDG_CONFIG_HOME").map(|dir| dir.join("rabi")),
+        home.map(|dir| dir.join(".config").join("rabi")),
+        exe.and_then(|exe| Some(exe.parent()?.join("config"))),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+// The location and contents of every configuration file by name, from the first directory that
+// has it or from the embedded defaults. Files that can't be read are reported as errors.
+pub fn config_files(config_dirs: &[PathBuf]) -> (BTreeMap<String, (String, String)>, Vec<String>) {
+    let mut files: BTreeMap<String, (String, String)> = DEFAULT_FILES
+        .iter()
+        .map(|(name, contents)| {
+            (
+                name.to_string(),
+                (format!("built-in {name}"), contents.to_string()),
+            )
+        })
+        .collect();
+    let mut errors = Vec::new();
+    for dir in config_dirs.iter().rev() {
+        // Missing directories are skipped
+        let Ok(dir_entries) = dir.read_dir() else {
+            continue;
+        };
+        for path in dir_entries.filter_map(|entry| Some(entry.ok()?.path())) {
+            if path.extension().is_none_or(|ext| ext != "ini") || !path.is_file() {
+                continue;
+            }
+            let location = path.display().to_string();
+            match fs::read_to_string(&path) {
+                Ok(contents) => {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    files.insert(name.to_string(), (location, contents));
+                }
+                Err(e) => errors.push(format!("{location}: {e}")),
+            }
+        }
+    }
+    (files, errors)
+}
+
+// Stamps of the `*.ini` files of the configuration directories, to notice when one changes
+pub type ConfigStamps = Vec<(PathBuf, Option<(SystemTime, u64)>)>;
+
+pub fn config_stamps(config_dirs: &[PathBuf]) -> ConfigStamps {
+    let mut stamps = Vec::new();
+    for dir in config_dirs {
+        let Ok(dir_entries) = dir.read_dir() else {
+            continue;
+        };
+        let paths = dir_entries.filter_map(|entry| Some(entry.ok()?.path()));
+        let paths = paths.filter(|path| path.extension().is_some_and(|ext| ext == "ini"));
+        stamps.extend(paths.map(|path| {
+            let stamp = file_stamp(&path);
+            (path, stamp)
+        }));
+    }
+    stamps.sort();
+    stamps
+}
+
+// Every problem of `rabi.ini`, `keys.ini`, the syntax files and the themes
+pub fn check_config(config_dirs: &[PathBuf]) -> Vec<String> {
+    let mut problems = Config::check(config_dirs.to_vec())
+        .err()
+        .unwrap_or_default();
+    let (files, errors) = config_files(config_dirs);
+    problems.extend(errors);
+    problems.extend(check_themes(config_dirs));
+    for name in files.keys().filter(|name| is_syntax_file(name)) {
+        problems.extend(SyntaxConfig::parse(name, &files).err().unwrap_or_default());
+    }
+    problems
+}
+
+pub fn parse_ini(
+    location: &str,
+    contents: &str,
     func: &mut impl FnMut(&str, &str) -> Result<(), String>,
-) -> Result<(), String> {
-    // eprintln!("parse {}", path.display());
-    let file = File::open(path).map_err(|e| e.to_string())?;
-    for (i, line) in BufReader::new(file).lines().enumerate() {
-        let line = line.map_err(|e| e.to_string())?;
+) -> Result<(), Vec<String>> {
+    parse_ini_sections(
+        location,
+        contents,
+        &mut |section, key, value| match section {
+            None => func(key, value),
+            Some(section) => Err(format!("Unexpected section [{section}]")),
+        },
+    )
+}
+
+// Like `parse_ini`, also passing the `[section]` each key belongs to. The errors of all lines
+// are returned, prefixed with `location:line`.
+pub fn parse_ini_sections(
+    location: &str,
+    contents: &str,
+    func: &mut impl FnMut(Option<&str>, &str, &str) -> Result<(), String>,
+) -> Result<(), Vec<String>> {
+    let mut section = None;
+    let mut errors = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if let Some(name) = line
+            .trim()
+            .strip_prefix('[')
+            .and_then(|l| l.strip_suffix(']'))
+        {
+            section = Some(name);
+            continue;
+        }
         let mut parts = line.trim().splitn(2, '=');
-        match (parts.next(), parts.next()) {
-            (Some(""), None) | (None, _) => (),
-            (Some(comment), _) if comment.starts_with(&['#', ';'][..]) => (),
-            (Some(key), Some(value)) => func(key.trim_end(), value)?,
-            (Some(_), None) => {
-                return Err(format!("Syntax error on configuration file line {}", i + 1))
-            }
+        let result = match (parts.next(), parts.next()) {
+            (Some(""), None) | (None, _) => Ok(()),
+            (Some(comment), _) if comment.starts_with(&['#', ';'][..]) => Ok(()),
+            (Some(key), Some(value)) => func(section, key.trim_end(), value),
+            (Some(_), None) => Err("Syntax error, expected key=value".to_string()),
+        };
+        if let Err(e) = result {
+            errors.push(format!("{location}:{}: {e}", i + 1));
         }
     }
-    Ok(())
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
 }
 
 pub fn parse_value<T: FromStr<Err = impl Display>>(value: &str) -> Result<T, String> {
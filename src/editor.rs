@@ -1,55 +1,52 @@
 use std::{
-    ffi::OsStr,
     fmt::{Display, Write as _},
-    fs::{metadata, File},
-    io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write as _},
+    fs,
+    io::{self, ErrorKind, Read, Write as _},
     iter,
+    ops::Range,
     path::{Path, PathBuf},
     process::Command,
-    time::{Duration, Instant},
+    str,
+    time::{Duration, Instant, SystemTime},
 };
 
+use unicode_width::UnicodeWidthStr;
+
 use crate::{
     ansi_escape::*,
+    config_stamps,
     ctrl_key::*,
-    format_size, get_winsize_using_cursor, slice_find,
+    diff_summary, file_stamp, find_newer_swap, format_size, get_winsize_using_cursor, parse_value,
+    slice_find, swap_path,
     sys::{self, enable_raw_mode, monitor_winsize, set_terminal_mode, TerminalMode},
-    Config, HlState, Row, SyntaxConfig, HELP_MESSAGE,
+    write_atomic, Action, ArrowKey, Config, ConfigStamps, EditorConfig, Encoding, HexView, HlState,
+    IndentStyle, Key, LineEnding, Row, SyntaxConfig, BYTES_PER_ROW, UNNAMED,
 };
 
-#[derive(Clone, Copy)]
-enum Key {
-    Arrow(ArrowKey),
-    CtrlArrow(ArrowKey),
-    PageUp,
-    PageDown,
-    Home,
-    End,
-    Delete,
-    Escape,
-    Char(u8),
-}
-
-#[derive(Clone, Copy)]
-enum ArrowKey {
-    Left,
-    Right,
-    Up,
-    Down,
-}
-
 #[derive(Debug)]
 enum CommandMode {
     Save(String),
     Find(String, Cursor, Option<usize>),
     GoTo(String),
     Execute(String),
+    Command(String),
+    Confirm(String, ConfirmAction),
+    Recover(PathBuf, Option<String>),
 }
 
-fn process_command_key(mut buffer: String, key: Key) -> CommandState {
+// Actions waiting for a yes/no answer
+#[derive(Debug)]
+enum ConfirmAction {
+    SaveAs(String),
+    Reload,
+    Quit,
+}
+
+fn process_command_key(mut buffer: String, key: Key, action: Option<Action>) -> CommandState {
     match key {
         Key::Char(b'\r') => CommandState::Completed(buffer),
-        Key::Escape | Key::Char(EXIT) => CommandState::Cancelled,
+        Key::Escape => CommandState::Cancelled,
+        _ if action == Some(Action::Quit) => CommandState::Cancelled,
         Key::Char(BACKSPACE | DELETE_BIS) => {
             buffer.pop();
             CommandState::Active(buffer)
@@ -65,23 +62,33 @@ fn process_command_key(mut buffer: String, key: Key) -> CommandState {
 impl CommandMode {
     pub fn process_key(self, editor: &mut Editor, key: Key) -> Result<Option<Self>, String> {
         editor.status_message = None;
+        let key_action = editor.config.keys.action(key);
         match self {
-            Self::Save(buffer) => match process_command_key(buffer, key) {
+            Self::Save(buffer) => match process_command_key(buffer, key, key_action) {
                 CommandState::Active(buffer) => return Ok(Some(Self::Save(buffer))),
                 CommandState::Cancelled => editor.set_status("Save aborted".to_string()),
-                CommandState::Completed(file_name) => editor.save_as(&file_name)?,
+                CommandState::Completed(file_name) => {
+                    if editor.file_name.as_ref() != Some(&file_name)
+                        && Path::new(&file_name).exists()
+                    {
+                        return Ok(Some(Self::Confirm(
+                            format!("{file_name} already exists. Overwrite?"),
+                            ConfirmAction::SaveAs(file_name),
+                        )));
+                    }
+                    editor.save_as(&file_name)?
+                }
             },
             Self::Find(buffer, cursor, last_match) => {
                 if let Some(row) = last_match {
                     editor.rows[row].match_range = None;
                 }
-                match process_command_key(buffer, key) {
+                match process_command_key(buffer, key, key_action) {
                     CommandState::Active(query) => {
                         let (last_match, forward) = match key {
-                            Key::Arrow(ArrowKey::Right | ArrowKey::Down) | Key::Char(FIND) => {
-                                (last_match, true)
-                            }
+                            Key::Arrow(ArrowKey::Right | ArrowKey::Down) => (last_match, true),
                             Key::Arrow(ArrowKey::Left | ArrowKey::Up) => (last_match, false),
+                            _ if key_action == Some(Action::Find) => (last_match, true),
                             _ => (None, true),
                         };
                         let current_match = editor.find(&query, last_match, forward);
@@ -91,25 +98,27 @@ impl CommandMode {
                     CommandState::Completed(_) => (),
                 }
             }
-            Self::GoTo(buffer) => match process_command_key(buffer, key) {
+            Self::GoTo(buffer) => match process_command_key(buffer, key, key_action) {
                 CommandState::Active(buffer) => return Ok(Some(Self::GoTo(buffer))),
                 CommandState::Cancelled => (),
+                CommandState::Completed(buffer) if editor.hex.is_some() => {
+                    let buffer = buffer.trim();
+                    let offset = match buffer.strip_prefix("0x") {
+                        Some(hex) => usize::from_str_radix(hex, 16),
+                        None => buffer.parse(),
+                    };
+                    match (offset, editor.hex.as_mut()) {
+                        (Ok(offset), Some(hex)) => hex.goto(offset),
+                        (Err(e), _) => editor.set_status(format!("GoTo error: {}", e)),
+                        _ => (),
+                    }
+                }
                 CommandState::Completed(buffer) => {
                     let mut split = buffer
                         .splitn(2, ':')
                         .map(|u| u.trim().parse::<usize>().map(|s| s.saturating_sub(1)));
                     match (split.next().transpose(), split.next().transpose()) {
-                        (Ok(Some(y)), Ok(x)) => {
-                            editor.cursor.y = y.min(editor.rows.len());
-                            editor.cursor.x = if let Some(rx) = x {
-                                editor.current_row().map_or(0, |r| r.r2c[rx])
-                            } else {
-                                editor
-                                    .cursor
-                                    .x
-                                    .min(editor.current_row().map_or(0, |r| r.chars.len()))
-                            }
-                        }
+                        (Ok(Some(y)), Ok(x)) => editor.go_to(y, x),
                         (Err(e), _) | (_, Err(e)) => {
                             editor.set_status(format!("GoTo error: {}", e))
                         }
@@ -117,7 +126,7 @@ impl CommandMode {
                     }
                 }
             },
-            Self::Execute(buffer) => match process_command_key(buffer, key) {
+            Self::Execute(buffer) => match process_command_key(buffer, key, key_action) {
                 CommandState::Active(buffer) => return Ok(Some(Self::Execute(buffer))),
                 CommandState::Cancelled => (),
                 CommandState::Completed(command) => {
@@ -139,17 +148,97 @@ impl CommandMode {
                     }
                 }
             },
+            Self::Command(buffer) => match process_command_key(buffer, key, key_action) {
+                CommandState::Active(buffer) => return Ok(Some(Self::Command(buffer))),
+                CommandState::Cancelled => (),
+                CommandState::Completed(command) => {
+                    if let Err(e) = editor.run_command(&command) {
+                        editor.set_status(e);
+                    }
+                }
+            },
+            Self::Confirm(prompt, action) => match key {
+                Key::Char(b'y' | b'Y') => match action {
+                    ConfirmAction::SaveAs(file_name) => editor.save_as(&file_name)?,
+                    // The buffer is kept when the file can't be read anymore
+                    ConfirmAction::Reload => {
+                        if let Err(e) = editor.reload() {
+                            editor.set_status(format!("Reload I/O error: {e}"));
+                        }
+                    }
+                    ConfirmAction::Quit => editor.quit = true,
+                },
+                _ if matches!(key, Key::Char(b'n' | b'N') | Key::Escape)
+                    || key_action == Some(Action::Quit) =>
+                {
+                    if let ConfirmAction::SaveAs(_) = action {
+                        editor.set_status("Save aborted".to_string())
+                    }
+                }
+                _ => return Ok(Some(Self::Confirm(prompt, action))),
+            },
+            // I/O errors are reported, the buffer is left as it is
+            Self::Recover(swap, diff) => match key {
+                Key::Char(b'r' | b'R') => {
+                    if let Err(e) = editor.recover(&swap) {
+                        editor.set_status(format!("Swap I/O error: {e}"));
+                    }
+                }
+                Key::Char(b'd' | b'D') => {
+                    let diff = match fs::read(&swap) {
+                        Ok(swap_data) => diff_summary(&editor.contents(), &swap_data),
+                        Err(e) => format!("Swap I/O error: {e}"),
+                    };
+                    return Ok(Some(Self::Recover(swap, Some(diff))));
+                }
+                Key::Char(b'x' | b'X') => editor.set_status(match fs::remove_file(&swap) {
+                    Ok(()) => "Swap file discarded".to_string(),
+                    Err(e) => format!("Swap I/O error: {e}"),
+                }),
+                // The swap is left for a later session, until the next backup replaces it
+                Key::Escape => editor.set_status(format!(
+                    "Swap file kept as {} until the next backup",
+                    swap.display()
+                )),
+                _ => return Ok(Some(Self::Recover(swap, diff))),
+            },
         }
         Ok(None)
     }
 }
 
+// Arrow key of the final byte `A` to `D` of an escape sequence
+fn arrow_key(c: u8) -> ArrowKey {
+    match c {
+        b'A' => ArrowKey::Up,
+        b'B' => ArrowKey::Down,
+        b'C' => ArrowKey::Right,
+        _ => ArrowKey::Left,
+    }
+}
+
 enum CommandState {
     Active(String),
     Completed(String),
     Cancelled,
 }
 
+// Options for opening a file
+#[derive(Default, Clone, Debug)]
+pub struct OpenOptions {
+    // Restore the unsaved changes from the swap file
+    pub recover: bool,
+    // Show the file as a hex dump, even if it is not binary
+    pub hex: bool,
+    // Refuse any change to the file
+    pub readonly: bool,
+    // Name of the syntax to use instead of the one matching the file extension
+    pub syntax: Option<String>,
+    // Line and column to put the cursor at, starting from 1
+    pub line: Option<usize>,
+    pub col: Option<usize>,
+}
+
 // Cursor position, 0-indexed
 #[derive(Default, Clone, Debug)]
 struct Cursor {
@@ -157,15 +246,26 @@ struct Cursor {
     y: usize,
     row_offset: usize,
     col_offset: usize,
+    // First visual line shown of the row at `row_offset`, when lines are wrapped
+    line_offset: usize,
 }
 
 #[derive(Default, Debug)]
 pub struct Editor {
     config: Config,
-    quit_times: usize,
+    quit: bool,
     file_name: Option<String>,
     syntax: SyntaxConfig,
+    // Syntaxes of the configuration directories with the errors of the broken files, and the
+    // stamps of the files they were parsed from
+    syntaxes: Vec<SyntaxConfig>,
+    syntax_errors: Vec<String>,
+    syntax_stamps: Option<ConfigStamps>,
     status_message: Option<(String, Instant)>,
+    swap_time: Option<Instant>,
+    // File stamp when the file was last read or written, and the last change we asked about
+    disk_stamp: Option<(SystemTime, u64)>,
+    seen_stamp: Option<(SystemTime, u64)>,
 
     cursor: Cursor,
     mode: Option<CommandMode>,
@@ -173,6 +273,14 @@ pub struct Editor {
     window_width: usize,
     rows: Vec<Row>,
     dirty: bool,
+    readonly: bool,
+    encoding: Encoding,
+    line_ending: LineEnding,
+    final_newline: bool,
+    editorconfig: EditorConfig,
+    // Indentation style detected in the file
+    indent_style: Option<IndentStyle>,
+    hex: Option<HexView>,
 
     // Editor size, excluding padding and bar
     text_rows: usize,
@@ -180,17 +288,20 @@ pub struct Editor {
     n_bytes: usize,
     origin_ternimal_mode: Option<TerminalMode>,
     copied_row: Vec<u8>,
+    // Row where the line selection started
+    selection: Option<usize>,
 }
 
 impl Editor {
     pub fn new(config: Config) -> Result<Self, String> {
         monitor_winsize()?;
         let mut editor = Self::default();
-        editor.quit_times = config.quit_times;
         editor.config = config;
         editor.origin_ternimal_mode = Some(enable_raw_mode()?);
         editor.update_winsize()?;
-        editor.set_status(HELP_MESSAGE.to_string());
+        let help = editor.config.keys.help();
+        editor.set_status(help);
+        print!("{ENABLE_FOCUS_REPORTING}");
         Ok(editor)
     }
 
@@ -219,9 +330,9 @@ impl Editor {
         let mut x = self.cursor.x;
         match (key, self.current_row()) {
             (ArrowKey::Left, Some(row)) if x > 0 => {
-                x -= row.get_char_size(row.c2r[x]);
+                x = row.prev_cx(x);
                 while ctrl && x > 0 && row.chars[x - 1] != b' ' {
-                    x -= row.get_char_size(row.c2r[x] - 1);
+                    x = row.prev_cx(x);
                 }
             }
             (ArrowKey::Left, _) if self.cursor.y > 0 => {
@@ -229,31 +340,120 @@ impl Editor {
                 self.cursor.y -= 1;
             }
             (ArrowKey::Right, Some(row)) if x < row.chars.len() => {
-                x += row.get_char_size(row.c2r[x]);
+                x = row.next_cx(x);
                 while ctrl && x < row.chars.len() && row.chars[x] != b' ' {
-                    x += row.get_char_size(row.c2r[x]);
+                    x = row.next_cx(x);
                 }
             }
             (ArrowKey::Right, Some(_)) => {
                 x = 0;
                 self.cursor.y += 1;
             }
+            (ArrowKey::Up, _) if self.soft_wrap() => return self.move_wrapped(true),
+            (ArrowKey::Down, _) if self.soft_wrap() => return self.move_wrapped(false),
             (ArrowKey::Up, _) if self.cursor.y > 0 => self.cursor.y -= 1,
-            (ArrowKey::Down, _) => self.cursor.y += 1,
+            (ArrowKey::Down, _) if self.cursor.y < self.rows.len() => self.cursor.y += 1,
             _ => (),
         }
         self.cursor.x = x.min(self.current_row().map_or(0, |row| row.chars.len()));
     }
 
-    fn select_syntax(&mut self, path: &Path) -> Result<(), String> {
-        if let Some(ext) = path.extension().and_then(OsStr::to_str) {
-            if let Some(syntax) = SyntaxConfig::from_ext(ext, &self.config.config_folder)? {
-                self.syntax = syntax;
-            }
+    // Move the cursor to row `y` and to the grapheme column `col`, if given
+    fn go_to(&mut self, y: usize, col: Option<usize>) {
+        self.cursor.y = y.min(self.rows.len());
+        let row_len = self.current_row().map_or(0, |r| r.chars.len());
+        self.cursor.x = match col {
+            Some(col) => self.current_row().map_or(0, |r| r.col2cx(col)),
+            None => self.cursor.x.min(row_len),
+        };
+    }
+
+    fn soft_wrap(&self) -> bool {
+        self.hex.is_none() && self.syntax.soft_wrap.unwrap_or(self.config.soft_wrap)
+    }
+
+    // Index of the visual line of the cursor in the wrapped current row, and its column there
+    fn wrapped_cursor(&self) -> (usize, usize) {
+        let rx = self.rx();
+        let starts = self
+            .current_row()
+            .map_or(vec![0], |row| row.wrap(self.text_cols));
+        let i = starts.iter().rposition(|s| *s <= rx).unwrap_or_default();
+        (i, rx - starts[i])
+    }
+
+    fn visual_lines(&self, rows: Range<usize>) -> usize {
+        let rows = rows.start.min(self.rows.len())..rows.end.min(self.rows.len());
+        self.rows[rows]
+            .iter()
+            .map(|row| row.wrap(self.text_cols).len())
+            .sum()
+    }
+
+    // Move the cursor to the previous or next visual line, staying in the same column
+    fn move_wrapped(&mut self, up: bool) {
+        let (line, col) = self.wrapped_cursor();
+        let n_lines = self.visual_lines(self.cursor.y..self.cursor.y + 1);
+        let line = if up && line > 0 {
+            line - 1
+        } else if up && self.cursor.y > 0 {
+            self.cursor.y -= 1;
+            self.visual_lines(self.cursor.y..self.cursor.y + 1) - 1
+        } else if !up && line + 1 < n_lines {
+            line + 1
+        } else if !up && self.cursor.y < self.rows.len() {
+            self.cursor.y += 1;
+            0
+        } else {
+            return;
+        };
+        self.cursor.x = self.current_row().map_or(0, |row| {
+            let starts = row.wrap(self.text_cols);
+            let end = starts.get(line + 1).map_or(row.width(), |s| s - 1);
+            row.r2c[(starts[line] + col).min(end)]
+        });
+    }
+
+    // Detect the syntax from the file name and contents. Modelines are looked for in the first
+    // and last 5 lines, like vim does.
+    fn select_syntax(&mut self, path: &Path) {
+        let n = self.rows.len();
+        let lines: Vec<String> = (0..n.min(5))
+            .chain(n.saturating_sub(5).max(5)..n)
+            .map(|y| String::from_utf8_lossy(&self.rows[y].chars).to_string())
+            .collect();
+        self.load_syntaxes();
+        if let Some(syntax) = SyntaxConfig::detect(&self.syntaxes, path, &lines) {
+            self.syntax = syntax.clone();
+            self.update_all_rows();
         }
+    }
+
+    // Set the syntax named `name`
+    fn set_syntax(&mut self, name: &str) -> Result<(), String> {
+        self.load_syntaxes();
+        let syntax = SyntaxConfig::from_name(&self.syntaxes, name);
+        self.syntax = syntax
+            .ok_or_else(|| format!("Unknown syntax: {name}"))?
+            .clone();
         Ok(())
     }
 
+    // Parse the syntax files again only when one of the configuration files changed. Broken
+    // syntax files are skipped, with a warning pointing to the first problem.
+    fn load_syntaxes(&mut self) {
+        let stamps = config_stamps(&self.config.config_dirs);
+        if self.syntax_stamps.as_ref() != Some(&stamps) {
+            (self.syntaxes, self.syntax_errors) = SyntaxConfig::all(&self.config.config_dirs);
+            self.syntax_stamps = Some(stamps);
+        }
+        if let Some(error) = self.syntax_errors.first() {
+            self.set_status(format!(
+                "Warning: {error} (run rabi --check-config for details)"
+            ));
+        }
+    }
+
     fn delete_char(&mut self) {
         if self.cursor.x > 0 {
             let row = &mut self.rows[self.cursor.y];
@@ -280,21 +480,246 @@ impl Editor {
     }
 
     fn insert_new_line(&mut self) {
-        let (column, chars) = if self.cursor.x == 0 {
-            (self.cursor.y, vec![])
+        let (column, chars, indent) = if self.cursor.x == 0 {
+            (self.cursor.y, vec![], 0)
         } else {
+            // A word closing a block, like `end`, is only known to be complete now
+            if self.closes_block(&self.rows[self.cursor.y].chars[..self.cursor.x]) {
+                self.dedent_closing_row(self.cursor.y);
+            }
             let new_chars = self.rows[self.cursor.y].chars.split_off(self.cursor.x);
             self.update_row(self.cursor.y, false);
-            (self.cursor.y + 1, new_chars)
+            // Copy the indentation of the line, one level deeper after a block opening
+            let row = &self.rows[self.cursor.y];
+            let mut indent = row.indentation().to_vec();
+            if self.opens_block(&row.chars) {
+                indent.extend(self.indent_unit());
+            }
+            let rest = new_chars.trim_ascii_start();
+            if self.closes_block(rest) {
+                indent = self.dedent(&indent);
+            }
+            self.n_bytes =
+                (self.n_bytes + indent.len() + rest.len()).saturating_sub(new_chars.len());
+            (self.cursor.y + 1, [&indent, rest].concat(), indent.len())
         };
         self.rows.insert(column, Row::new(chars));
         self.update_row(column, false);
         self.update_padding();
-        self.cursor.x = 0;
+        self.cursor.x = indent;
         self.cursor.y += 1;
         self.dirty = true;
     }
 
+    // Word triggers like `do` must not be the end of a longer word like `undo`
+    fn opens_block(&self, chars: &[u8]) -> bool {
+        let chars = chars.trim_ascii_end();
+        (self.syntax.indent_increase.iter()).any(|s| {
+            let rest = chars.strip_suffix(s.as_bytes());
+            rest.is_some_and(|rest| match (rest.last(), s.as_bytes().first()) {
+                (Some(&before), Some(&first)) => Row::is_sep(before) || Row::is_sep(first),
+                _ => true,
+            })
+        })
+    }
+
+    // Word triggers like `end` must not be the start of a longer word like `endpoint`
+    fn closes_block(&self, chars: &[u8]) -> bool {
+        let chars = chars.trim_ascii_start();
+        (self.syntax.indent_decrease.iter()).any(|s| {
+            let rest = chars.strip_prefix(s.as_bytes());
+            rest.is_some_and(|rest| match (rest.first(), s.as_bytes().last()) {
+                (Some(&after), Some(&last)) => Row::is_sep(after) || Row::is_sep(last),
+                _ => true,
+            })
+        })
+    }
+
+    // The indentation style of the buffer takes precedence over `.editorconfig` files, which
+    // take precedence over the settings of the language
+    fn expand_tab(&self) -> bool {
+        match self.indent_style {
+            Some(style) => style != IndentStyle::Tabs,
+            None => (self.editorconfig.expand_tab())
+                .or(self.syntax.expand_tab)
+                .unwrap_or(self.config.expand_tab),
+        }
+    }
+
+    fn indent_size(&self) -> usize {
+        match self.indent_style {
+            Some(IndentStyle::Spaces(n)) => n,
+            Some(IndentStyle::Tabs) => self.tab_stop(),
+            None => (self.editorconfig.indent_size())
+                .or(self.syntax.indent_size)
+                .unwrap_or(self.config.indent_size),
+        }
+    }
+
+    fn tab_stop(&self) -> usize {
+        self.editorconfig
+            .tab_width()
+            .unwrap_or(self.config.tab_stop)
+    }
+
+    fn load_editorconfig(&mut self, path: &Path) {
+        match EditorConfig::load(path) {
+            Ok(editorconfig) => self.editorconfig = editorconfig,
+            Err(e) => self.set_status(format!("Invalid .editorconfig: {e}")),
+        }
+    }
+
+    fn trim_trailing_whitespace(&mut self) {
+        for y in 0..self.rows.len() {
+            let chars = &mut self.rows[y].chars;
+            let n = chars.len() - chars.trim_ascii_end().len();
+            if n > 0 {
+                chars.truncate(chars.len() - n);
+                self.n_bytes -= n;
+                self.update_row(y, true);
+            }
+        }
+        self.cursor.x = self
+            .cursor
+            .x
+            .min(self.current_row().map_or(0, |r| r.chars.len()));
+    }
+
+    // Convert the indentation of every row to the given style, keeping the indentation levels
+    fn reindent(&mut self, style: IndentStyle) {
+        let old_unit = if self.expand_tab() {
+            self.indent_size()
+        } else {
+            self.tab_stop()
+        };
+        self.indent_style = Some(style);
+        let unit = self.indent_unit();
+        for y in 0..self.rows.len() {
+            let row = &self.rows[y];
+            let old_len = row.indentation().len();
+            let width = row.c2r[old_len];
+            let indent = [unit.repeat(width / old_unit), vec![b' '; width % old_unit]].concat();
+            if indent != row.indentation() {
+                self.n_bytes = (self.n_bytes + indent.len()).saturating_sub(old_len);
+                if y == self.cursor.y {
+                    self.cursor.x = (self.cursor.x + indent.len()).saturating_sub(old_len);
+                }
+                self.rows[y].chars.splice(..old_len, indent);
+                self.update_row(y, true);
+                self.dirty = true;
+            }
+        }
+        self.set_status(format!("Reindented with {}", self.indent_description()));
+    }
+
+    fn indent_description(&self) -> String {
+        if self.expand_tab() {
+            format!("Spaces: {}", self.indent_size())
+        } else {
+            format!("Tabs: {}", self.tab_stop())
+        }
+    }
+
+    // One level of indentation
+    fn indent_unit(&self) -> Vec<u8> {
+        if self.expand_tab() {
+            vec![b' '; self.indent_size()]
+        } else {
+            vec![b'\t']
+        }
+    }
+
+    // Remove one level from the given indentation
+    fn dedent(&self, indent: &[u8]) -> Vec<u8> {
+        let spaces = indent.iter().rev().take_while(|c| **c == b' ').count();
+        let n = match spaces {
+            0 => usize::from(!indent.is_empty()),
+            _ => (spaces - 1) % self.indent_size() + 1,
+        };
+        indent[..indent.len() - n].to_vec()
+    }
+
+    // Insert spaces up to the next indentation level, or a tab
+    fn insert_tab(&mut self) {
+        if self.expand_tab() {
+            let n = self.indent_size() - self.rx() % self.indent_size();
+            (0..n).for_each(|_| self.insert_byte(b' '));
+        } else {
+            self.insert_byte(b'\t');
+        }
+    }
+
+    // Indent or dedent the selected rows, or the current row
+    fn indent_rows(&mut self, dedent: bool) {
+        let y = self.cursor.y;
+        let rows = self
+            .selected_rows()
+            .unwrap_or(y..(y + 1).min(self.rows.len()));
+        for y in rows {
+            let row = &self.rows[y];
+            let old_len = row.indentation().len();
+            let indent = if dedent {
+                self.dedent(row.indentation())
+            } else if row.chars.is_empty() {
+                continue;
+            } else {
+                [row.indentation(), &self.indent_unit()].concat()
+            };
+            self.n_bytes = (self.n_bytes + indent.len()).saturating_sub(old_len);
+            if y == self.cursor.y {
+                self.cursor.x = (self.cursor.x + indent.len()).saturating_sub(old_len);
+            }
+            self.rows[y].chars.splice(..old_len, indent);
+            self.update_row(y, true);
+            self.dirty = true;
+        }
+    }
+
+    // Delete a whole indentation level when the cursor is after spaces only. Returns false if
+    // there is nothing to delete this way.
+    fn delete_indent_level(&mut self) -> bool {
+        let (x, indent_size) = (self.cursor.x, self.indent_size());
+        if !self.expand_tab() || x == 0 {
+            return false;
+        }
+        let Some(row) = self.rows.get_mut(self.cursor.y) else {
+            return false;
+        };
+        if row.chars[..x].iter().any(|c| *c != b' ') {
+            return false;
+        }
+        let n = (x - 1) % indent_size + 1;
+        row.chars.splice(x - n..x, iter::empty());
+        self.update_row(self.cursor.y, true);
+        self.cursor.x -= n;
+        self.n_bytes -= n;
+        self.dirty = true;
+        true
+    }
+
+    // Dedent row `y` when it starts a line closing the block of the previous line
+    fn dedent_closing_row(&mut self, y: usize) {
+        let Some(prev) = self.rows[..y]
+            .iter()
+            .rev()
+            .find(|r| !r.chars.trim_ascii().is_empty())
+        else {
+            return;
+        };
+        let mut indent = prev.indentation().to_vec();
+        if self.opens_block(&prev.chars) {
+            indent.extend(self.indent_unit());
+        }
+        let indent = self.dedent(&indent);
+        let old_len = self.rows[y].indentation().len();
+        if indent.len() < old_len {
+            self.rows[y].chars.splice(..old_len, indent.iter().copied());
+            self.update_row(y, false);
+            self.cursor.x -= old_len - indent.len();
+            self.n_bytes -= old_len - indent.len();
+        }
+    }
+
     fn delete_current_row(&mut self) {
         if self.cursor.y < self.rows.len() {
             self.rows[self.cursor.y].chars.clear();
@@ -345,48 +770,477 @@ impl Editor {
         self.cursor.x += 1;
         self.n_bytes += 1;
         self.dirty = true;
+        // Punctuation like `}` dedents the line as soon as it is typed, and words like `end` once
+        // a separator follows, so that `endpoint` is left alone
+        let row = &self.rows[self.cursor.y];
+        let typed = row.chars[..self.cursor.x].trim_ascii_start();
+        let closing = (self.syntax.indent_decrease.iter()).any(|s| match s.as_bytes() {
+            [.., last] if Row::is_sep(*last) => s.as_bytes() == typed,
+            s => Row::is_sep(c) && typed.strip_suffix(&[c]) == Some(s),
+        });
+        if closing {
+            self.dedent_closing_row(self.cursor.y);
+        }
     }
 
-    fn save(&self, file_name: &str) -> Result<usize, String> {
-        let mut file = File::create(file_name).map_err(|e| e.to_string())?;
-        let mut n = 0;
+    fn contents(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.file_size());
         for (i, row) in self.rows.iter().enumerate() {
-            file.write_all(&row.chars).map_err(|e| e.to_string())?;
-            n += row.chars.len();
-            if i != self.rows.len() - 1 {
-                file.write_all(&[b'\n']).map_err(|e| e.to_string())?;
-                n += 1;
+            if i != 0 {
+                data.extend_from_slice(self.line_ending.as_bytes());
             }
+            data.extend_from_slice(&row.chars);
+        }
+        if self.ends_with_newline(data.is_empty()) {
+            data.extend_from_slice(self.line_ending.as_bytes());
+        }
+        data
+    }
+
+    // Whether the saved file ends with a newline. An explicit `insert_final_newline` from
+    // `.editorconfig` adds or removes it, while `rabi.ini` only adds it.
+    fn ends_with_newline(&self, empty: bool) -> bool {
+        match self.editorconfig.insert_final_newline() {
+            Some(insert) => insert && !empty,
+            None => self.final_newline || (self.config.insert_final_newline && !empty),
+        }
+    }
+
+    // The bytes written to disk on save
+    fn file_data(&self) -> Result<Vec<u8>, String> {
+        match &self.hex {
+            Some(hex) => Ok(hex.bytes.clone()),
+            None => self.encoding.encode(&self.contents()),
         }
-        file.sync_all().map_err(|e| e.to_string())?;
-        Ok(n)
+    }
+
+    fn file_size(&self) -> usize {
+        if let Some(hex) = &self.hex {
+            return hex.bytes.len();
+        }
+        // Size on disk, in the encoding the file is saved in
+        let empty = self.rows.len() <= 1 && self.rows.iter().all(|row| row.chars.is_empty());
+        let n_newlines =
+            self.rows.len().saturating_sub(1) + usize::from(self.ends_with_newline(empty));
+        let text = match self.encoding {
+            Encoding::Utf8 | Encoding::Utf8Bom => self.n_bytes,
+            encoding => (self.rows.iter())
+                .map(|row| encoding.encoded_len(&row.chars))
+                .sum(),
+        };
+        let newline = self.encoding.encoded_len(self.line_ending.as_bytes());
+        self.encoding.bom().len() + text + n_newlines * newline
+    }
+
+    fn load(&mut self, data: Vec<u8>, hex: bool) {
+        if hex || HexView::is_binary(&data) {
+            self.rows.clear();
+            self.hex = Some(HexView::new(data));
+            self.cursor = Cursor::default();
+            self.update_padding();
+        } else {
+            self.set_contents(&data);
+        }
+    }
+
+    fn set_contents(&mut self, data: &[u8]) {
+        self.hex = None;
+        let (encoding, data) = Encoding::decode(data);
+        let data = data.as_slice();
+        self.encoding = encoding;
+        self.line_ending = LineEnding::detect(data);
+        self.final_newline = data.last() == Some(&b'\n');
+        let data = data.strip_suffix(b"\n").unwrap_or(data);
+        self.rows = data
+            .split(|c| *c == b'\n')
+            .map(|line| match self.line_ending {
+                LineEnding::CrLf => line.strip_suffix(b"\r").unwrap_or(line),
+                LineEnding::Lf => line,
+            })
+            .map(|line| Row::new(line.to_vec()))
+            .collect();
+        // Settings from `.editorconfig` files are converted to when saving
+        self.line_ending = self.editorconfig.end_of_line().unwrap_or(self.line_ending);
+        self.encoding = self.editorconfig.charset().unwrap_or(self.encoding);
+        self.indent_style = if self.editorconfig.expand_tab().is_some()
+            || self.editorconfig.indent_size().is_some()
+        {
+            None
+        } else {
+            IndentStyle::detect(self.rows.iter().map(|row| row.chars.as_slice()))
+        };
+        self.update_all_rows();
+        self.update_padding();
+        self.n_bytes = self.rows.iter().map(|row| row.chars.len()).sum();
+        self.cursor = Cursor::default();
+    }
+
+    fn save(&self, file_name: &str) -> Result<usize, String> {
+        let data = self.file_data()?;
+        write_atomic(Path::new(file_name), &data, false)?;
+        Ok(data.len())
     }
 
     fn handle_save(&mut self, file_name: &str) -> bool {
+        if self.editorconfig.trim_trailing_whitespace() {
+            self.trim_trailing_whitespace();
+        }
         let saved = self.save(file_name);
         self.set_status(match saved.as_ref() {
             Ok(n) => format!("{} written to {}", format_size(*n), file_name),
             Err(e) => format!("Save I/O error: {}", e),
         });
+        if saved.is_ok() {
+            self.remove_swap();
+            self.disk_stamp = file_stamp(Path::new(file_name));
+            self.seen_stamp = None;
+        }
         self.dirty &= saved.is_err();
         saved.is_ok()
     }
 
+    // Periodically back up unsaved changes to the swap file
+    fn update_swap(&mut self) {
+        let interval = Duration::from_secs(self.config.swap_interval as u64);
+        if !self.dirty
+            || interval.is_zero()
+            || self.swap_time.is_some_and(|t| t.elapsed() < interval)
+        {
+            return;
+        }
+        // Text is backed up in UTF-8, so that it survives characters the encoding can't store
+        let data = self
+            .hex
+            .as_ref()
+            .map_or_else(|| self.contents(), |h| h.bytes.clone());
+        // The swap may hold the unsaved contents of a file others can't read
+        if let Err(e) = write_atomic(&self.swap_file(), &data, true) {
+            self.set_status(format!("Swap I/O error: {e}"));
+        }
+        self.swap_time = Some(Instant::now());
+    }
+
+    fn swap_file(&self) -> PathBuf {
+        swap_path(Path::new(self.file_name.as_deref().unwrap_or(UNNAMED)))
+    }
+
+    // Only a swap written in this session is removed, one kept from a previous session stays
+    fn remove_swap(&mut self) {
+        if self.swap_time.is_some() {
+            let _ = fs::remove_file(self.swap_file());
+        }
+        self.swap_time = None;
+    }
+
+    // The current stamp of the file, if it differs from the one we last read or wrote
+    fn disk_change(&self) -> Option<(SystemTime, u64)> {
+        let stamp = file_stamp(Path::new(self.file_name.as_deref()?));
+        stamp.filter(|_| stamp != self.disk_stamp)
+    }
+
+    // Offer to reload the file when another program rewrote it, asking only once per change
+    fn check_disk_changes(&mut self) -> bool {
+        if self.mode.is_some()
+            || self
+                .disk_change()
+                .is_none_or(|s| Some(s) == self.seen_stamp)
+        {
+            return false;
+        }
+        self.seen_stamp = self.disk_change();
+        let prompt = if self.dirty {
+            "File changed on disk and the buffer is modified. Reload and discard your changes?"
+        } else {
+            "File changed on disk. Reload?"
+        };
+        self.mode = Some(CommandMode::Confirm(
+            prompt.to_string(),
+            ConfirmAction::Reload,
+        ));
+        true
+    }
+
+    fn reload(&mut self) -> Result<(), String> {
+        if let Some(file_name) = self.file_name.clone() {
+            let cursor = self.cursor.clone();
+            let hex_cursor = self.hex.as_ref().map(|hex| hex.cursor);
+            let data = fs::read(&file_name).map_err(|e| e.to_string())?;
+            self.load(data, self.hex.is_some());
+            self.cursor = cursor;
+            if let (Some(hex), Some(hex_cursor)) = (self.hex.as_mut(), hex_cursor) {
+                hex.cursor = hex_cursor.min(2 * hex.bytes.len());
+            }
+            self.cursor.y = self.cursor.y.min(self.rows.len());
+            self.cursor.x = self
+                .cursor
+                .x
+                .min(self.current_row().map_or(0, |r| r.chars.len()));
+            self.dirty = false;
+            self.disk_stamp = file_stamp(Path::new(&file_name));
+            self.seen_stamp = None;
+            self.set_status(format!("Reloaded {file_name}"));
+        }
+        Ok(())
+    }
+
+    fn recover(&mut self, swap: &Path) -> Result<(), String> {
+        // Swap files are written in UTF-8, keep the encoding of the original file
+        let encoding = self.encoding;
+        self.load(
+            fs::read(swap).map_err(|e| e.to_string())?,
+            self.hex.is_some(),
+        );
+        self.encoding = encoding;
+        self.dirty = true;
+        // The swap holds the buffer, so it is removed once the buffer is saved
+        self.swap_time = Some(Instant::now());
+        self.set_status("Recovered unsaved changes from swap file".to_string());
+        Ok(())
+    }
+
     fn save_as(&mut self, file_name: &str) -> Result<(), String> {
         if self.handle_save(file_name) {
-            self.select_syntax(Path::new(file_name))?;
+            self.load_editorconfig(Path::new(file_name));
             self.file_name = Some(file_name.to_string());
             self.update_all_rows();
+            self.select_syntax(Path::new(file_name));
+        }
+        Ok(())
+    }
+
+    // Commands entered after ^P, e.g. `eol crlf`
+    fn run_command(&mut self, command: &str) -> Result<(), String> {
+        let selection = self.selected_rows();
+        self.selection = None;
+        let mut args = command.split_whitespace();
+        match (args.next(), args.next()) {
+            (Some("eol" | "encoding" | "reindent"), Some(_)) | (Some("reflow"), _)
+                if self.readonly =>
+            {
+                return Err("File is read-only".to_string())
+            }
+            (Some("eol"), Some(line_ending)) => {
+                self.line_ending = line_ending.parse()?;
+                self.dirty = true;
+                self.set_status(format!("Line endings set to {}", self.line_ending));
+            }
+            (Some("eol"), None) => self.set_status(format!("Line endings: {}", self.line_ending)),
+            (Some("encoding"), Some(encoding)) => {
+                let (old, new) = (self.encoding, encoding.parse()?);
+                self.encoding = new;
+                // Rows that are not valid UTF-8 are read again as the Latin-1 text they were. An
+                // unmodified file detected as Latin-1 is read again as UTF-8, as it was on disk.
+                let utf8 = matches!(new, Encoding::Utf8 | Encoding::Utf8Bom);
+                if new == Encoding::Latin1 || (utf8 && old == Encoding::Latin1 && !self.dirty) {
+                    for row in &mut self.rows {
+                        row.chars = match new {
+                            Encoding::Latin1 if str::from_utf8(&row.chars).is_err() => {
+                                Encoding::decode_latin1(&row.chars)
+                            }
+                            Encoding::Latin1 => continue,
+                            _ => old.encode(&row.chars)?,
+                        };
+                    }
+                    self.update_all_rows();
+                    self.n_bytes = self.rows.iter().map(|row| row.chars.len()).sum();
+                }
+                self.dirty = true;
+                self.set_status(format!("Encoding set to {}", self.encoding));
+            }
+            (Some("encoding"), None) => self.set_status(format!("Encoding: {}", self.encoding)),
+            (Some("hex"), None) => match self.hex.take() {
+                Some(hex) => self.set_contents(&hex.bytes),
+                None => {
+                    let data = self.file_data()?;
+                    self.load(data, true);
+                }
+            },
+            (Some("reindent"), Some(style)) if self.hex.is_none() => {
+                let style = match (style, args.next().map(parse_value).transpose()?) {
+                    ("tabs", None) => IndentStyle::Tabs,
+                    ("spaces", Some(0)) => return Err("Indentation size must be positive".into()),
+                    ("spaces", n) => IndentStyle::Spaces(n.unwrap_or(self.indent_size())),
+                    _ => return Err("Usage: reindent tabs | reindent spaces [N]".to_string()),
+                };
+                self.reindent(style);
+            }
+            (Some("reindent"), None) => self.set_status(self.indent_description()),
+            (Some("reflow"), None) if self.hex.is_none() => self.reflow(selection),
+            (Some("syntax"), Some(name)) => {
+                self.set_syntax(name)?;
+                self.update_all_rows();
+                self.set_status(format!("Syntax set to {}", self.syntax.name));
+            }
+            (Some("syntax"), None) => {
+                self.load_syntaxes();
+                let names: Vec<_> = self.syntaxes.iter().map(|s| s.name.as_str()).collect();
+                self.set_status(format!("Syntaxes: {}", names.join(", ")));
+            }
+            (Some(command), _) => return Err(format!("Unknown command: {command}")),
+            (None, _) => (),
         }
         Ok(())
     }
 
-    fn process_key(&mut self, key: Key) -> (bool, Option<CommandMode>) {
-        let mut quit_times = self.config.quit_times;
-        let mut command = None;
+    // Rows covered by the line selection, from its anchor to the cursor
+    fn selected_rows(&self) -> Option<Range<usize>> {
+        let anchor = self.selection?;
+        let (start, end) = (anchor.min(self.cursor.y), anchor.max(self.cursor.y));
+        Some(start..(end + 1).min(self.rows.len()))
+    }
+
+    // Length of the indentation and comment start at the beginning of `chars`
+    fn line_prefix_len(&self, chars: &[u8]) -> usize {
+        let indent = chars.iter().take_while(|c| c.is_ascii_whitespace()).count();
+        let comment = (self.syntax.slcomment_start.iter())
+            .find(|s| chars[indent..].starts_with(s.as_bytes()))
+            .map_or(0, String::len);
+        let rest = &chars[indent + comment..];
+        indent + comment + rest.iter().take_while(|c| c.is_ascii_whitespace()).count()
+    }
+
+    // Rewrap the given rows, or the paragraph around the cursor, to the text width. Every line
+    // starts with the indentation and comment prefix of the first one.
+    fn reflow(&mut self, rows: Option<Range<usize>>) {
+        let is_blank = |row: &Row| self.line_prefix_len(&row.chars) == row.chars.len();
+        let rows = rows.unwrap_or_else(|| {
+            let (mut start, mut end) = (self.cursor.y, self.cursor.y);
+            if self.current_row().is_none_or(is_blank) {
+                return start..end;
+            }
+            while start > 0 && !is_blank(&self.rows[start - 1]) {
+                start -= 1;
+            }
+            while end < self.rows.len() && !is_blank(&self.rows[end]) {
+                end += 1;
+            }
+            start..end
+        });
+        let Some(first) = self.rows.get(rows.start).filter(|_| !rows.is_empty()) else {
+            return self.set_status("Nothing to reflow".to_string());
+        };
+        let prefix = &first.chars[..self.line_prefix_len(&first.chars)];
+        let text_width = self.syntax.text_width.unwrap_or(self.config.text_width);
+
+        let mut lines: Vec<Vec<u8>> = Vec::new();
+        let mut width = 0;
+        for row in &self.rows[rows.clone()] {
+            let text = &row.chars[self.line_prefix_len(&row.chars)..];
+            for word in text
+                .split(u8::is_ascii_whitespace)
+                .filter(|w| !w.is_empty())
+            {
+                let n = str::from_utf8(word).map_or(word.len(), UnicodeWidthStr::width);
+                match lines.last_mut() {
+                    Some(line) if width + 1 + n <= text_width => {
+                        line.push(b' ');
+                        line.extend(word);
+                        width += 1 + n;
+                    }
+                    _ => {
+                        lines.push([prefix, word].concat());
+                        width = first.c2r[prefix.len()] + n;
+                    }
+                }
+            }
+        }
+        if lines.is_empty() {
+            return self.set_status("Nothing to reflow".to_string());
+        }
+
+        let old_bytes: usize = self.rows[rows.clone()].iter().map(|r| r.chars.len()).sum();
+        let new_bytes: usize = lines.iter().map(Vec::len).sum();
+        self.n_bytes = (self.n_bytes + new_bytes).saturating_sub(old_bytes);
+        let n = lines.len();
+        self.rows
+            .splice(rows.clone(), lines.into_iter().map(Row::new));
+        for y in rows.start..rows.start + n {
+            self.update_row(y, true);
+        }
+        if rows.start + n < self.rows.len() {
+            self.update_row(rows.start + n, false);
+        }
+        self.cursor.y = rows.start + n - 1;
+        self.cursor.x = self.rows[self.cursor.y].chars.len();
+        self.update_padding();
+        self.dirty = true;
+        self.set_status(format!("Reflowed {} lines into {n}", rows.len()));
+    }
+
+    // Keys of the hex view. Returns false for the keys that work the same as in text mode.
+    fn process_hex_key(&mut self, key: Key) -> bool {
+        let row_len = 2 * BYTES_PER_ROW as isize;
+        let page = self.text_rows as isize * row_len;
+        let action = self.config.keys.action(key);
+        let Some(hex) = self.hex.as_mut() else {
+            return false;
+        };
+        let column = hex.cursor as isize % row_len;
+        match key {
+            _ if matches!(
+                action,
+                Some(
+                    Action::Quit | Action::Save | Action::GoTo | Action::Command | Action::Refresh
+                )
+            ) =>
+            {
+                return false
+            }
+            Key::Escape | Key::FocusIn | Key::FocusOut => return false,
+            Key::Arrow(arrow) | Key::CtrlArrow(arrow) | Key::ShiftArrow(arrow) => {
+                hex.move_cursor(match arrow {
+                    ArrowKey::Left => -1,
+                    ArrowKey::Right => 1,
+                    ArrowKey::Up => -row_len,
+                    ArrowKey::Down => row_len,
+                })
+            }
+            Key::PageUp => hex.move_cursor(-page),
+            Key::PageDown => hex.move_cursor(page),
+            Key::Home => hex.move_cursor(-column),
+            Key::End => hex.move_cursor(row_len - 1 - column),
+            Key::Delete => self.dirty |= hex.delete_byte(hex.offset()),
+            Key::Char(BACKSPACE | DELETE_BIS) if hex.offset() > 0 => {
+                self.dirty |= hex.delete_byte(hex.offset() - 1)
+            }
+            Key::Char(c) if c.is_ascii_hexdigit() => {
+                hex.set_nibble((c as char).to_digit(16).unwrap_or_default() as u8);
+                self.dirty = true;
+            }
+            _ => self.set_status("Not available in hex mode".to_string()),
+        }
+        true
+    }
+
+    fn process_key(&mut self, key: Key) -> Option<CommandMode> {
+        let action = self.config.keys.action(key);
+        let edits = match (key, action) {
+            (_, Some(action)) => action.edits(),
+            (Key::Delete | Key::BackTab | Key::Char(_), None) => true,
+            _ => false,
+        };
+        if self.readonly && edits {
+            self.set_status("File is read-only".to_string());
+            return None;
+        }
+        if self.process_hex_key(key) {
+            return None;
+        }
+        if let Some(action) = action {
+            let command = self.run_action(action);
+            // The selection is kept for commands entered after ^P
+            if action != Action::Command {
+                self.selection = None;
+            }
+            return command;
+        }
         match key {
             Key::Arrow(arrow) => self.move_cursor(arrow, false),
             Key::CtrlArrow(arrow) => self.move_cursor(arrow, true),
+            Key::ShiftArrow(arrow) => {
+                self.selection.get_or_insert(self.cursor.y);
+                self.move_cursor(arrow, false);
+            }
             Key::PageUp => {
                 self.cursor.y = self.cursor.row_offset.saturating_sub(self.text_rows);
                 self.cursor.x = self
@@ -408,45 +1262,67 @@ impl Editor {
                 self.move_cursor(ArrowKey::Right, false);
                 self.delete_char();
             }
-            Key::Escape => (),
+            Key::Escape | Key::FocusIn | Key::FocusOut | Key::Alt(_) | Key::Function(_) => (),
             Key::Char(b'\r' | b'\n') => self.insert_new_line(),
-            Key::Char(BACKSPACE | DELETE_BIS) => self.delete_char(),
-            Key::Char(REMOVE_LINE) => self.delete_current_row(),
-            Key::Char(REFRESH_SCREEN) => (),
-            Key::Char(EXIT) => {
-                quit_times = self.quit_times - 1;
-                if !self.dirty || quit_times == 0 {
-                    return (true, None);
+            Key::Char(BACKSPACE | DELETE_BIS) => {
+                if !self.delete_indent_level() {
+                    self.delete_char();
                 }
-                self.set_status(format!("Press Ctrl+Q {quit_times} more time(s) to quit."));
             }
-            Key::Char(SAVE) => {
-                if let Some(file_name) = self.file_name.take() {
-                    self.handle_save(&file_name);
-                    self.file_name = Some(file_name);
-                } else {
-                    command = Some(CommandMode::Save(String::new()))
+            Key::Char(b'\t') if self.selection.is_some() => self.indent_rows(false),
+            Key::Char(b'\t') => self.insert_tab(),
+            Key::BackTab => self.indent_rows(true),
+            Key::Char(c) => self.insert_byte(c),
+        }
+        // The selection is kept for indentation changes
+        if !matches!(key, Key::ShiftArrow(_) | Key::BackTab | Key::Char(b'\t')) {
+            self.selection = None;
+        }
+        None
+    }
+
+    fn run_action(&mut self, action: Action) -> Option<CommandMode> {
+        match action {
+            Action::RemoveLine => self.delete_current_row(),
+            Action::Refresh => (),
+            Action::Quit if self.dirty => {
+                return Some(CommandMode::Confirm(
+                    "File has unsaved changes. Quit anyway?".to_string(),
+                    ConfirmAction::Quit,
+                ))
+            }
+            Action::Quit => self.quit = true,
+            Action::Save => {
+                return match self.file_name.clone() {
+                    Some(file_name) if self.disk_change().is_some() => Some(CommandMode::Confirm(
+                        "File changed on disk since it was last read. Overwrite?".to_string(),
+                        ConfirmAction::SaveAs(file_name),
+                    )),
+                    Some(file_name) => {
+                        self.handle_save(&file_name);
+                        None
+                    }
+                    None => Some(CommandMode::Save(String::new())),
                 }
             }
-            Key::Char(FIND) => {
-                command = Some(CommandMode::Find(String::new(), self.cursor.clone(), None))
+            Action::Find => {
+                return Some(CommandMode::Find(String::new(), self.cursor.clone(), None))
             }
-            Key::Char(GOTO) => command = Some(CommandMode::GoTo(String::new())),
-            Key::Char(DUPLICATE) => self.duplicate_current_row(),
-            Key::Char(CUT) => {
+            Action::GoTo => return Some(CommandMode::GoTo(String::new())),
+            Action::Duplicate => self.duplicate_current_row(),
+            Action::Cut => {
                 self.copy_current_row();
                 self.delete_current_row();
             }
-            Key::Char(COPY) => self.copy_current_row(),
-            Key::Char(PASTE) => {
+            Action::Copy => self.copy_current_row(),
+            Action::Paste => {
                 self.paste_current_row();
                 self.set_status("Paste!".to_string());
             }
-            Key::Char(EXECUTE) => command = Some(CommandMode::Execute(String::new())),
-            Key::Char(c) => self.insert_byte(c),
+            Action::Execute => return Some(CommandMode::Execute(String::new())),
+            Action::Command => return Some(CommandMode::Command(String::new())),
         }
-        self.quit_times = quit_times;
-        (false, command)
+        None
     }
 
     fn update_row(&mut self, y: usize, ignore_following: bool) {
@@ -455,9 +1331,10 @@ impl Editor {
         } else {
             HlState::Normal
         };
+        let tab_stop = self.tab_stop();
         for row in self.rows.iter_mut().skip(y) {
             let pre_hl_state = row.hl_state;
-            hl_state = row.update(&self.syntax, hl_state, self.config.tab_stop);
+            hl_state = row.update(&self.syntax, hl_state, tab_stop);
             if ignore_following || hl_state == pre_hl_state {
                 return;
             }
@@ -465,19 +1342,21 @@ impl Editor {
     }
 
     fn update_all_rows(&mut self) {
-        let mut hl_state = HlState::Normal;
+        let (mut hl_state, tab_stop) = (HlState::Normal, self.tab_stop());
         for row in &mut self.rows {
-            hl_state = row.update(&self.syntax, hl_state, self.config.tab_stop);
+            hl_state = row.update(&self.syntax, hl_state, tab_stop);
         }
     }
 
     fn update_padding(&mut self) {
         let n = self.rows.len().to_string().len();
-        self.left_padding = if self.config.show_line_numbers && n + 2 < self.window_width / 4 {
-            n + 2
-        } else {
-            0
-        };
+        self.left_padding =
+            if self.config.show_line_numbers && self.hex.is_none() && n + 2 < self.window_width / 4
+            {
+                n + 2
+            } else {
+                0
+            };
         self.text_cols = self.window_width.saturating_sub(self.left_padding);
     }
 
@@ -485,7 +1364,8 @@ impl Editor {
         if self.left_padding >= 2 {
             write!(
                 buffer,
-                "\x1b[38;5;240m{:>2$} \u{2502}{}",
+                "{}{:>3$} \u{2502}{}",
+                self.config.theme.gutter,
                 val,
                 RESET_FMT,
                 self.left_padding - 2
@@ -496,22 +1376,58 @@ impl Editor {
     }
 
     fn draw_rows(&self, buffer: &mut String) -> Result<(), String> {
-        for (i, row) in self
-            .rows
-            .iter()
+        if let Some(hex) = &self.hex {
+            for y in (self.cursor.row_offset..).take(self.text_rows) {
+                buffer.push_str(CLEAR_LINE_RIGHT_OF_CURSOR);
+                if y < hex.n_rows() {
+                    hex.draw_row(y, self.cursor.col_offset, self.text_cols, buffer)?;
+                } else {
+                    buffer.push('~');
+                }
+                buffer.push_str("\r\n");
+            }
+            return Ok(());
+        }
+        let keyword_styles = self
+            .config
+            .theme
+            .keyword_styles(&self.syntax.keyword_classes);
+        // Visual lines: row index, row, index of the line in the row, and its render columns
+        let wrap = self.soft_wrap();
+        let lines = self.rows.iter().enumerate().skip(self.cursor.row_offset);
+        let lines = lines.flat_map(|(i, row)| {
+            let (starts, ends) = if wrap {
+                let starts = row.wrap(self.text_cols);
+                let ends = starts[1..].iter().copied().chain([row.width()]).collect();
+                (starts, ends)
+            } else {
+                let offset = self.cursor.col_offset;
+                (vec![offset], vec![offset + self.text_cols])
+            };
+            let ranges = starts.into_iter().zip(ends).enumerate();
+            ranges.map(move |(j, range)| (i, row, j, range))
+        });
+        let lines = lines.skip(self.cursor.line_offset);
+        for (y, line) in lines
             .map(Some)
             .chain(iter::repeat(None))
-            .enumerate()
-            .skip(self.cursor.row_offset)
             .take(self.text_rows)
+            .enumerate()
         {
             buffer.push_str(CLEAR_LINE_RIGHT_OF_CURSOR);
-            if let Some(row) = row {
-                self.draw_padding(buffer, i + 1)?;
-                row.draw(self.cursor.col_offset, self.text_cols, buffer)?;
+            if let Some((i, row, j, (start, end))) = line {
+                if j == 0 {
+                    self.draw_padding(buffer, i + 1)?;
+                } else {
+                    // Continuation marker for wrapped lines
+                    self.draw_padding(buffer, '\u{21aa}')?;
+                }
+                let selected = self.selected_rows().is_some_and(|r| r.contains(&i));
+                let theme = &self.config.theme;
+                row.draw(start, end - start, selected, theme, &keyword_styles, buffer)?;
             } else {
                 self.draw_padding(buffer, '~')?;
-                if self.rows.len() <= 1 && self.n_bytes == 0 && i == self.text_rows / 3 {
+                if self.rows.len() <= 1 && self.n_bytes == 0 && y == self.text_rows / 3 {
                     write!(
                         buffer,
                         "{:^1$.1$}",
@@ -529,20 +1445,40 @@ impl Editor {
         let mut left = format!(
             "{:.30}{}",
             self.file_name.as_deref().unwrap_or("[No Name]"),
-            if self.dirty { " (modified)" } else { "" }
+            if self.dirty {
+                " (modified)"
+            } else if self.readonly {
+                " (read-only)"
+            } else {
+                ""
+            }
         );
         left.truncate(self.window_width);
-        let right = format!(
-            "{} | {} | {}:{}",
-            self.syntax.name,
-            format_size(self.n_bytes + self.rows.len().saturating_sub(1)),
-            self.cursor.y + 1,
-            self.rx() + 1
-        );
+        let right = if let Some(hex) = &self.hex {
+            format!(
+                "Hex | {} | {:#x}",
+                format_size(self.file_size()),
+                hex.offset()
+            )
+        } else {
+            format!(
+                "{} | {} | {} | {} | {} | {}:{}",
+                self.syntax.name,
+                self.indent_description(),
+                self.encoding,
+                self.line_ending,
+                format_size(self.file_size()),
+                self.cursor.y + 1,
+                self.current_row()
+                    .map_or(0, |row| row.cx2col(self.cursor.x))
+                    + 1
+            )
+        };
         let rw = self.window_width.saturating_sub(left.len());
         write!(
             buffer,
-            "{REVERSE_VIDEO}{left}{right:>rw$.rw$}{RESET_FMT}\r\n"
+            "{}{left}{right:>rw$.rw$}{RESET_FMT}\r\n",
+            self.config.theme.status_bar
         )
         .map_err(|e| e.to_string())?;
         Ok(())
@@ -558,16 +1494,62 @@ impl Editor {
     }
 
     fn refresh(&mut self) -> Result<(), String> {
-        self.cursor.row_offset = self.cursor.row_offset.clamp(
-            self.cursor
-                .y
-                .saturating_sub(self.text_rows.saturating_sub(1)),
-            self.cursor.y,
-        );
-        self.cursor.col_offset = self.cursor.col_offset.clamp(
-            self.rx().saturating_sub(self.text_cols.saturating_sub(1)),
-            self.rx(),
-        );
+        if let Some(mode) = self.mode.as_ref() {
+            self.set_status(match &mode {
+                CommandMode::Save(s) => format!("Save as {s}"),
+                CommandMode::Find(s, ..) => format!("Search (Use ESC/Arrows/Enter): {s}"),
+                CommandMode::GoTo(s) if self.hex.is_some() => format!("Enter byte offset: {s}"),
+                CommandMode::GoTo(s) => format!("Enter line number[:column number]: {s}"),
+                CommandMode::Execute(s) => format!("CommandMode to execute: {s}"),
+                CommandMode::Command(s) => format!("Command: {s}"),
+                CommandMode::Confirm(s, _) => format!("{s} (y/n)"),
+                CommandMode::Recover(_, diff) => format!(
+                    "{}Swap file found: (r)ecover, (d)iff, (x) discard or (Esc) keep?",
+                    diff.as_ref().map_or(String::new(), |d| format!("{d}. "))
+                ),
+            })
+        }
+        let (rx, y) = if self.soft_wrap() {
+            // Scroll by visual lines until the one of the cursor fits on the screen, and express
+            // its position as if every visual line was a row
+            let (line, col) = self.wrapped_cursor();
+            let (y, cursor) = (self.cursor.y, &self.cursor);
+            let (mut offset, mut line_offset) =
+                if (y, line) < (cursor.row_offset, cursor.line_offset) {
+                    (y, line)
+                } else if cursor.row_offset < y.saturating_sub(self.text_rows) {
+                    (y.saturating_sub(self.text_rows), 0)
+                } else {
+                    (cursor.row_offset, cursor.line_offset)
+                };
+            let row_lines = |i: usize| self.rows.get(i).map_or(1, |r| r.wrap(self.text_cols).len());
+            line_offset = line_offset.min(row_lines(offset) - 1);
+            let mut distance = self.visual_lines(offset..y) + line - line_offset;
+            while distance > 0 && distance >= self.text_rows {
+                line_offset += 1;
+                if line_offset == row_lines(offset) {
+                    (offset, line_offset) = (offset + 1, 0);
+                }
+                distance -= 1;
+            }
+            self.cursor.col_offset = 0;
+            self.cursor.row_offset = offset;
+            self.cursor.line_offset = line_offset;
+            (col.min(self.text_cols.saturating_sub(1)), offset + distance)
+        } else {
+            self.cursor.line_offset = 0;
+            self.hex
+                .as_ref()
+                .map_or((self.rx(), self.cursor.y), HexView::cursor_pos)
+        };
+        self.cursor.row_offset = self
+            .cursor
+            .row_offset
+            .clamp(y.saturating_sub(self.text_rows.saturating_sub(1)), y);
+        self.cursor.col_offset = self
+            .cursor
+            .col_offset
+            .clamp(rx.saturating_sub(self.text_cols.saturating_sub(1)), rx);
 
         let mut buffer = format!("{HIDE_CURSOR}{MOVE_CURSOR_TO_START}");
         self.draw_rows(&mut buffer)?;
@@ -576,8 +1558,8 @@ impl Editor {
 
         let (cursor_x, cursor_y) = if self.mode.is_none() {
             (
-                self.rx() - self.cursor.col_offset + 1 + self.left_padding,
-                self.cursor.y - self.cursor.row_offset + 1,
+                rx - self.cursor.col_offset + 1 + self.left_padding,
+                y - self.cursor.row_offset + 1,
             )
         } else {
             (
@@ -594,53 +1576,68 @@ impl Editor {
 
     fn wait_for_key(&mut self) -> Result<Key, String> {
         loop {
+            self.update_swap();
             if sys::winsize_changed() {
                 self.update_winsize()?;
                 self.refresh()?;
             }
+            if self.check_disk_changes() {
+                self.refresh()?;
+            }
             let mut bytes = io::stdin().bytes();
             match bytes.next().transpose().map_err(|e| e.to_string())? {
                 Some(b'\x1b') => {
-                    return Ok(match bytes.next().transpose().map_err(|e| e.to_string())? {
-                        Some(b @ (b'[' | b'O')) => {
-                            match (b, bytes.next().transpose().map_err(|e| e.to_string())?) {
-                                (b'[', Some(b'A')) => Key::Arrow(ArrowKey::Up),
-                                (b'[', Some(b'B')) => Key::Arrow(ArrowKey::Down),
-                                (b'[', Some(b'C')) => Key::Arrow(ArrowKey::Right),
-                                (b'[', Some(b'D')) => Key::Arrow(ArrowKey::Left),
-                                (b'[' | b'O', Some(b'H')) => Key::Home,
-                                (b'[' | b'O', Some(b'F')) => Key::End,
-                                (b'[', mut c @ Some(b'0'..=b'8')) => {
-                                    let mut d =
-                                        bytes.next().transpose().map_err(|e| e.to_string())?;
-                                    if let (Some(b'1'), Some(b';')) = (c, d) {
-                                        c = bytes.next().transpose().map_err(|e| e.to_string())?;
-                                        d = bytes.next().transpose().map_err(|e| e.to_string())?;
-                                    }
-                                    match (c, d) {
-                                        (Some(c), Some(b'~')) if c == b'1' || c == b'7' => {
-                                            Key::Home
-                                        }
-                                        (Some(c), Some(b'~')) if c == b'4' || c == b'8' => Key::End,
-                                        (Some(b'3'), Some(b'~')) => Key::Delete,
-                                        (Some(b'5'), Some(b'~')) => Key::PageUp,
-                                        (Some(b'6'), Some(b'~')) => Key::PageDown,
-                                        (Some(b'5'), Some(b'A')) => Key::CtrlArrow(ArrowKey::Up),
-                                        (Some(b'5'), Some(b'B')) => Key::CtrlArrow(ArrowKey::Down),
-                                        (Some(b'5'), Some(b'C')) => Key::CtrlArrow(ArrowKey::Right),
-                                        (Some(b'5'), Some(b'D')) => Key::CtrlArrow(ArrowKey::Left),
-                                        _ => Key::Escape,
+                    let key = match bytes.next().transpose().map_err(|e| e.to_string())? {
+                        Some(b'[') => {
+                            // Numeric parameters, then the final byte of the sequence
+                            let mut params = String::new();
+                            let last = loop {
+                                match bytes.next().transpose().map_err(|e| e.to_string())? {
+                                    Some(c @ (b'0'..=b'9' | b';')) => params.push(c as char),
+                                    c => break c,
+                                }
+                            };
+                            match (params.as_str(), last) {
+                                ("" | "1", Some(c @ b'A'..=b'D')) => Key::Arrow(arrow_key(c)),
+                                ("1;5", Some(c @ b'A'..=b'D')) => Key::CtrlArrow(arrow_key(c)),
+                                ("1;2", Some(c @ b'A'..=b'D')) => Key::ShiftArrow(arrow_key(c)),
+                                ("", Some(b'Z')) => Key::BackTab,
+                                ("", Some(b'I')) => Key::FocusIn,
+                                ("", Some(b'O')) => Key::FocusOut,
+                                ("" | "1", Some(b'H')) | ("1" | "7", Some(b'~')) => Key::Home,
+                                ("" | "1", Some(b'F')) | ("4" | "8", Some(b'~')) => Key::End,
+                                ("3", Some(b'~')) => Key::Delete,
+                                ("5", Some(b'~')) => Key::PageUp,
+                                ("6", Some(b'~')) => Key::PageDown,
+                                (n, Some(b'~')) => {
+                                    // F1 to F12 are numbered 11 to 24, skipping 16 and 22
+                                    const CODES: [&str; 12] = [
+                                        "11", "12", "13", "14", "15", "17", "18", "19", "20", "21",
+                                        "23", "24",
+                                    ];
+                                    match CODES.iter().position(|code| *code == n) {
+                                        Some(i) => Key::Function(i as u8 + 1),
+                                        None => Key::Escape,
                                     }
                                 }
-                                (b'O', Some(b'a')) => Key::CtrlArrow(ArrowKey::Up),
-                                (b'O', Some(b'b')) => Key::CtrlArrow(ArrowKey::Down),
-                                (b'O', Some(b'c')) => Key::CtrlArrow(ArrowKey::Right),
-                                (b'O', Some(b'd')) => Key::CtrlArrow(ArrowKey::Left),
                                 _ => Key::Escape,
                             }
                         }
-                        _ => Key::Escape,
-                    });
+                        Some(b'O') => match bytes.next().transpose().map_err(|e| e.to_string())? {
+                            Some(b'H') => Key::Home,
+                            Some(b'F') => Key::End,
+                            Some(c @ b'a'..=b'd') => Key::CtrlArrow(arrow_key(c - b'a' + b'A')),
+                            Some(c @ b'P'..=b'S') => Key::Function(c - b'P' + 1),
+                            _ => Key::Escape,
+                        },
+                        // Terminals send Alt+key as Escape followed by the key
+                        Some(c) => Key::Alt(c),
+                        None => Key::Escape,
+                    };
+                    // Focus changes only trigger the checks at the top of the loop
+                    if !matches!(key, Key::FocusIn | Key::FocusOut) {
+                        return Ok(key);
+                    }
                 }
                 Some(c) => return Ok(Key::Char(c)),
                 None => (),
@@ -658,67 +1655,60 @@ impl Editor {
                 self.cursor.x = cx;
                 self.cursor.y = current;
                 self.cursor.col_offset = 0;
-                row.match_range = Some(row.c2r[cx]..row.c2r[cx] + query.len());
+                row.match_range = Some(row.c2r[cx]..row.c2r[cx + query.len()]);
                 return Some(current);
             }
         }
         None
     }
 
-    pub fn run(&mut self, filename: Option<String>) -> Result<(), String> {
+    pub fn run(&mut self, filename: Option<String>, options: &OpenOptions) -> Result<(), String> {
+        if let Some(name) = &options.syntax {
+            self.set_syntax(name)?;
+        }
+        self.readonly = options.readonly;
         if let Some(path) = filename.map(PathBuf::from) {
             self.file_name = Some(path.to_string_lossy().to_string());
             let path = path.as_path();
-            self.select_syntax(path)?;
-            let ft = metadata(path).map_err(|e| e.to_string())?.file_type();
-            if !ft.is_file() && !ft.is_symlink() {
-                return Err("Invalid file".to_string());
-            }
-            match File::open(path) {
-                Ok(file) => {
-                    for line in BufReader::new(file).split(b'\n') {
-                        self.rows.push(Row::new(line.map_err(|e| e.to_string())?));
-                    }
-
-                    let mut file = File::open(path).map_err(|e| e.to_string())?;
-                    file.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
-                    if file
-                        .bytes()
-                        .next()
-                        .transpose()
-                        .map_err(|e| e.to_string())?
-                        .map_or(true, |b| b == b'\n')
-                    {
-                        self.rows.push(Row::new(vec![]));
-                    }
-                    self.update_all_rows();
-                    self.update_padding();
-                    self.n_bytes = self.rows.iter().map(|row| row.chars.len()).sum();
+            self.load_editorconfig(path);
+            match fs::read(path) {
+                Ok(data) => {
+                    self.load(data, options.hex);
+                    self.disk_stamp = file_stamp(path);
                 }
-                Err(e) if e.kind() == ErrorKind::NotFound => (),
-                Err(e) => return Err(e.to_string()),
+                Err(e) if e.kind() == ErrorKind::NotFound => self.load(Vec::new(), options.hex),
+                Err(e) => return Err(format!("{}: {e}", path.display())),
+            }
+            if options.syntax.is_none() {
+                self.select_syntax(path);
             }
         } else {
             self.file_name = None;
-            self.rows.push(Row::new(vec![]));
+            self.load(Vec::new(), options.hex);
+        }
+        let path = self.file_name.clone().unwrap_or(UNNAMED.to_string());
+        match find_newer_swap(Path::new(&path)) {
+            Some(swap) if options.recover => self.recover(&swap)?,
+            Some(swap) => self.mode = Some(CommandMode::Recover(swap, None)),
+            None if options.recover => return Err(format!("No swap file found for {path}")),
+            None => (),
+        }
+        if let Some(line) = options.line.filter(|_| self.hex.is_none()) {
+            self.go_to(
+                line.saturating_sub(1),
+                options.col.map(|c| c.saturating_sub(1)),
+            );
         }
         loop {
-            if let Some(mode) = self.mode.as_ref() {
-                self.set_status(match &mode {
-                    CommandMode::Save(s) => format!("Save as {s}"),
-                    CommandMode::Find(s, ..) => format!("Search (Use ESC/Arrows/Enter): {s}"),
-                    CommandMode::GoTo(s) => format!("Enter line number[:column number]: {s}"),
-                    CommandMode::Execute(s) => format!("CommandMode to execute: {s}"),
-                })
-            }
             self.refresh()?;
             let key = self.wait_for_key()?;
             self.mode = match self.mode.take() {
                 Some(mode) => mode.process_key(self, key)?,
-                None => match self.process_key(key) {
-                    (true, _) => return Ok(()),
-                    (false, mode) => mode,
-                },
+                None => self.process_key(key),
+            };
+            if self.quit {
+                self.remove_swap();
+                return Ok(());
             }
         }
     }
@@ -730,7 +1720,7 @@ impl Drop for Editor {
             set_terminal_mode(mode).expect("Failed to restore original terminal mode.");
         }
         if !std::thread::panicking() {
-            print!("{CLEAR_SCREEN}{MOVE_CURSOR_TO_START}");
+            print!("{DISABLE_FOCUS_REPORTING}{CLEAR_SCREEN}{MOVE_CURSOR_TO_START}");
             io::stdout().flush().expect("Failed to flush stdout.");
         }
     }
@@ -0,0 +1,263 @@
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use crate::{parse_ini_sections, Encoding, LineEnding};
+
+// Properties of the `.editorconfig` files that apply to a file, see https://editorconfig.org
+#[derive(Default, Debug)]
+pub struct EditorConfig(HashMap<String, String>);
+
+impl EditorConfig {
+    // Read the `.editorconfig` files from the directory of `path` up to the root one. Closer
+    // files take precedence.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let path = std::path::absolute(path).map_err(|e| e.to_string())?;
+        let mut files = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            let file = dir.join(".editorconfig");
+            if !file.is_file() {
+                continue;
+            }
+            let (mut root, mut properties) = (false, Vec::new());
+            let relative = path.strip_prefix(dir).unwrap_or(&path);
+            let contents = fs::read_to_string(&file).map_err(|e| e.to_string())?;
+            let location = file.display().to_string();
+            parse_ini_sections(&location, &contents, &mut |section, key, value| {
+                let (key, value) = (key.to_lowercase(), value.trim().to_lowercase());
+                match section {
+                    None => root |= key == "root" && value == "true",
+                    Some(glob) if Self::matches(glob, relative) => properties.push((key, value)),
+                    Some(_) => (),
+                }
+                Ok(())
+            })
+            .map_err(|errors| errors.join("; "))?;
+            files.push(properties);
+            if root {
+                break;
+            }
+        }
+        let mut config = Self::default();
+        for (key, value) in files.into_iter().rev().flatten() {
+            if value == "unset" {
+                config.0.remove(&key);
+            } else {
+                config.0.insert(key, value);
+            }
+        }
+        Ok(config)
+    }
+
+    // Globs without `/` match the file name in any directory, others the path relative to the
+    // directory of the `.editorconfig` file
+    fn matches(glob: &str, relative: &Path) -> bool {
+        let path = relative.to_string_lossy().replace('\\', "/");
+        let (glob, path) = match glob.strip_prefix('/') {
+            Some(glob) => (glob, path.as_str()),
+            None if glob.contains('/') => (glob, path.as_str()),
+            None => (glob, path.rsplit('/').next().unwrap_or_default()),
+        };
+        let path: Vec<char> = path.chars().collect();
+        expand_braces(glob)
+            .iter()
+            .any(|glob| glob_match(&glob.chars().collect::<Vec<_>>(), &path))
+    }
+
+    fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.0.get(key)?.parse().ok()
+    }
+
+    fn get_size(&self, key: &str) -> Option<usize> {
+        self.get(key).filter(|n| *n > 0)
+    }
+
+    pub fn expand_tab(&self) -> Option<bool> {
+        match self.0.get("indent_style")?.as_str() {
+            "space" => Some(true),
+            "tab" => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn indent_size(&self) -> Option<usize> {
+        match self.0.get("indent_size")?.as_str() {
+            "tab" => self.tab_width(),
+            _ => self.get_size("indent_size"),
+        }
+    }
+
+    pub fn tab_width(&self) -> Option<usize> {
+        self.get_size("tab_width")
+            .or_else(|| self.get_size("indent_size"))
+    }
+
+    pub fn end_of_line(&self) -> Option<LineEnding> {
+        self.get("end_of_line")
+    }
+
+    pub fn charset(&self) -> Option<Encoding> {
+        self.get("charset")
+    }
+
+    pub fn trim_trailing_whitespace(&self) -> bool {
+        self.get("trim_trailing_whitespace").unwrap_or(false)
+    }
+
+    pub fn insert_final_newline(&self) -> Option<bool> {
+        self.get("insert_final_newline")
+    }
+}
+
+// Expand `{a,b}` alternatives and `{1..3}` ranges of a glob
+fn expand_braces(glob: &str) -> Vec<String> {
+    let Some(start) = glob.find('{') else {
+        return vec![glob.to_string()];
+    };
+    let mut depth = 0;
+    let end = glob[start..].char_indices().find_map(|(i, c)| {
+        depth += match c {
+            '{' => 1,
+            '}' => -1,
+            _ => 0,
+        };
+        (depth == 0).then_some(start + i)
+    });
+    let Some(end) = end else {
+        return vec![glob.to_string()];
+    };
+    let (prefix, inner, suffix) = (&glob[..start], &glob[start + 1..end], &glob[end + 1..]);
+    let range = inner
+        .split_once("..")
+        .and_then(|(a, b)| Some((a.parse::<i64>().ok()?, b.parse::<i64>().ok()?)));
+    let alternatives: Vec<String> = if let Some((a, b)) = range {
+        (a.min(b)..=a.max(b)).map(|n| n.to_string()).collect()
+    } else {
+        // Split on the commas that are not nested in other braces
+        let (mut parts, mut depth, mut part) = (Vec::new(), 0, String::new());
+        for c in inner.chars() {
+            match c {
+                ',' if depth == 0 => parts.push(std::mem::take(&mut part)),
+                '{' | '}' => {
+                    depth += if c == '{' { 1 } else { -1 };
+                    part.push(c);
+                }
+                _ => part.push(c),
+            }
+        }
+        parts.push(part);
+        if parts.len() == 1 {
+            // A single alternative is taken literally
+            let alternatives = expand_braces(inner).into_iter();
+            alternatives.map(|alt| format!("{{{alt}}}")).collect()
+        } else {
+            parts.iter().flat_map(|part| expand_braces(part)).collect()
+        }
+    };
+    let suffixes = expand_braces(suffix);
+    alternatives
+        .iter()
+        .flat_map(|alt| suffixes.iter().map(move |s| format!("{prefix}{alt}{s}")))
+        .collect()
+}
+
+// Match `path` against a glob made of `*`, `**`, `?`, `[...]`, `[!...]` and literal characters
+fn glob_match(glob: &[char], path: &[char]) -> bool {
+    match glob {
+        [] => path.is_empty(),
+        // `**/` also matches no directory at all
+        ['*', '*', '/', rest @ ..] if glob_match(rest, path) => true,
+        ['*', '*', rest @ ..] => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+        ['*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| glob_match(rest, &path[i..])),
+        ['?', rest @ ..] => path.first().is_some_and(|c| *c != '/') && glob_match(rest, &path[1..]),
+        ['[', rest @ ..] if rest.contains(&']') => {
+            let end = rest.iter().position(|c| *c == ']').unwrap_or_default();
+            let (negate, class) = match &rest[..end] {
+                ['!', class @ ..] => (true, class),
+                class => (false, class),
+            };
+            let Some(&c) = path.first().filter(|c| **c != '/') else {
+                return false;
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if class.get(i + 1) == Some(&'-') && i + 2 < class.len() {
+                    matched |= (class[i]..=class[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            matched != negate && glob_match(&rest[end + 1..], &path[1..])
+        }
+        ['\\', c, rest @ ..] | [c, rest @ ..] => {
+            path.first() == Some(c) && glob_match(rest, &path[1..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, path: &str) -> bool {
+        EditorConfig::matches(glob, Path::new(path))
+    }
+
+    #[test]
+    fn braces() {
+        assert_eq!(expand_braces("*.{rs,toml}"), ["*.rs", "*.toml"]);
+        assert_eq!(expand_braces("{a,{b,c}d}"), ["a", "bd", "cd"]);
+        assert_eq!(expand_braces("{a,b}{1..2}"), ["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand_braces("{3..1}"), ["1", "2", "3"]);
+        // A single alternative and unclosed braces are literal
+        assert_eq!(expand_braces("{single}"), ["{single}"]);
+        assert_eq!(expand_braces("{a,b"), ["{a,b"]);
+    }
+
+    #[test]
+    fn file_names_match_in_any_directory() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", "src/main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("{*.js,Makefile}", "lib/Makefile"));
+        assert!(matches("file{1..3}.txt", "file2.txt"));
+        assert!(!matches("file{1..3}.txt", "file4.txt"));
+    }
+
+    #[test]
+    fn paths_match_from_the_editorconfig_directory() {
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/bin/main.rs"));
+        assert!(matches("/src/**.rs", "src/bin/main.rs"));
+        assert!(!matches("/src/*.rs", "lib/src/main.rs"));
+    }
+
+    #[test]
+    fn double_star_slash_matches_any_directories() {
+        assert!(matches("**/test.py", "test.py"));
+        assert!(matches("**/test.py", "a/b/test.py"));
+        assert!(matches("a/**/z.c", "a/z.c"));
+        assert!(matches("a/**/z.c", "a/b/c/z.c"));
+        assert!(!matches("a/**/z.c", "b/z.c"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(matches("[abc].txt", "b.txt"));
+        assert!(!matches("[abc].txt", "d.txt"));
+        assert!(matches("[a-c]x", "cx"));
+        assert!(matches("[!x].txt", "a.txt"));
+        assert!(!matches("[!x].txt", "x.txt"));
+        assert!(matches("?.c", "a.c"));
+        assert!(!matches("?.c", "ab.c"));
+        // `*`, `?` and classes never match the separator
+        assert!(!matches("a*b/c", "a/b/c"));
+        // A class without a closing bracket is literal
+        assert!(matches("[ab", "[ab"));
+        assert!(matches("\\*.md", "*.md"));
+        assert!(!matches("\\*.md", "a.md"));
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+pub const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+pub const UTF16LE_BOM: &[u8] = b"\xff\xfe";
+pub const UTF16BE_BOM: &[u8] = b"\xfe\xff";
+
+// Encoding of a file on disk. Rows are always stored as UTF-8 in memory.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    // Detect the encoding of `data` and transcode it to UTF-8. Text that is not valid UTF-8 is
+    // Latin-1, as any byte sequence is valid Latin-1. Data with NUL bytes is kept as is, with the
+    // invalid bytes shown as replacement characters.
+    pub fn decode(data: &[u8]) -> (Self, Vec<u8>) {
+        if let Some(text) = data.strip_prefix(UTF8_BOM) {
+            if std::str::from_utf8(text).is_ok() {
+                return (Self::Utf8Bom, text.to_vec());
+            }
+        }
+        for (encoding, bom) in [(Self::Utf16Le, UTF16LE_BOM), (Self::Utf16Be, UTF16BE_BOM)] {
+            if let Some(text) = data
+                .strip_prefix(bom)
+                .and_then(|d| encoding.decode_utf16(d))
+            {
+                return (encoding, text);
+            }
+        }
+        if !data.contains(&0) && std::str::from_utf8(data).is_err() {
+            (Self::Latin1, Self::decode_latin1(data))
+        } else {
+            (Self::Utf8, data.to_vec())
+        }
+    }
+
+    pub fn decode_latin1(data: &[u8]) -> Vec<u8> {
+        data.iter().map(|&c| c as char).collect::<String>().into()
+    }
+
+    fn decode_utf16(self, data: &[u8]) -> Option<Vec<u8>> {
+        let chunks = data.chunks_exact(2);
+        if !chunks.remainder().is_empty() {
+            return None;
+        }
+        let units = chunks.map(|c| match self {
+            Self::Utf16Be => u16::from_be_bytes([c[0], c[1]]),
+            _ => u16::from_le_bytes([c[0], c[1]]),
+        });
+        char::decode_utf16(units)
+            .collect::<Result<String, _>>()
+            .ok()
+            .map(String::into_bytes)
+    }
+
+    pub const fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Utf8Bom => UTF8_BOM,
+            Self::Utf16Le => UTF16LE_BOM,
+            Self::Utf16Be => UTF16BE_BOM,
+            Self::Utf8 | Self::Latin1 => b"",
+        }
+    }
+
+    // Size of UTF-8 `text` once transcoded to this encoding, without the BOM
+    pub fn encoded_len(self, text: &[u8]) -> usize {
+        match self {
+            Self::Utf8 | Self::Utf8Bom => text.len(),
+            Self::Utf16Le | Self::Utf16Be => {
+                2 * String::from_utf8_lossy(text).encode_utf16().count()
+            }
+            Self::Latin1 => String::from_utf8_lossy(text).chars().count(),
+        }
+    }
+
+    // Transcode UTF-8 `text` back to this encoding. Invalid UTF-8 bytes read from a UTF-8 file
+    // are written back unchanged.
+    pub fn encode(self, text: &[u8]) -> Result<Vec<u8>, String> {
+        let utf8 = || std::str::from_utf8(text).map_err(|e| format!("Invalid UTF-8: {e}"));
+        Ok(match self {
+            Self::Utf8 | Self::Utf8Bom => [self.bom(), text].concat(),
+            Self::Utf16Le => UTF16LE_BOM
+                .iter()
+                .copied()
+                .chain(utf8()?.encode_utf16().flat_map(u16::to_le_bytes))
+                .collect(),
+            Self::Utf16Be => UTF16BE_BOM
+                .iter()
+                .copied()
+                .chain(utf8()?.encode_utf16().flat_map(u16::to_be_bytes))
+                .collect(),
+            Self::Latin1 => utf8()?
+                .chars()
+                .map(|c| u8::try_from(c).map_err(|_| format!("Cannot encode {c:?} in {self}")))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Utf8 => "UTF-8",
+            Self::Utf8Bom => "UTF-8 BOM",
+            Self::Utf16Le => "UTF-16LE",
+            Self::Utf16Be => "UTF-16BE",
+            Self::Latin1 => "Latin-1",
+        })
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Ok(Self::Utf8),
+            "utf-8-bom" | "utf8-bom" => Ok(Self::Utf8Bom),
+            "utf-16le" | "utf16le" => Ok(Self::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Self::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Self::Latin1),
+            _ => Err(format!("Unknown encoding: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = "caf\u{e9} \u{fffd}\n";
+        for (encoding, bytes) in [
+            (Encoding::Utf8, text.as_bytes().to_vec()),
+            (Encoding::Utf8Bom, [UTF8_BOM, text.as_bytes()].concat()),
+            (
+                Encoding::Utf16Le,
+                [UTF16LE_BOM, b"c\0a\0f\0\xe9\0 \0\xfd\xff\n\0"].concat(),
+            ),
+            (
+                Encoding::Utf16Be,
+                [UTF16BE_BOM, b"\0c\0a\0f\0\xe9\0 \xff\xfd\0\n"].concat(),
+            ),
+        ] {
+            assert_eq!(
+                Encoding::decode(&bytes),
+                (encoding, text.into()),
+                "{encoding}"
+            );
+            assert_eq!(encoding.encode(text.as_bytes()), Ok(bytes), "{encoding}");
+        }
+        let latin1 = b"caf\xe9 \xff\n";
+        let (encoding, text) = Encoding::decode(latin1);
+        assert_eq!(
+            (encoding, text.as_slice()),
+            (Encoding::Latin1, "café ÿ\n".as_bytes())
+        );
+        assert_eq!(encoding.encode(&text), Ok(latin1.to_vec()));
+    }
+
+    #[test]
+    fn invalid_data() {
+        // Data with NUL bytes stays as is, and is written back unchanged
+        let data = b"a\0\xff";
+        assert_eq!(Encoding::decode(data), (Encoding::Utf8, data.to_vec()));
+        assert_eq!(Encoding::Utf8.encode(data), Ok(data.to_vec()));
+        // A BOM followed by invalid data is not trusted
+        assert_eq!(Encoding::decode(b"\xff\xfea").0, Encoding::Latin1);
+        assert!(Encoding::Latin1.encode("\u{20ac}".as_bytes()).is_err());
+        assert!(Encoding::Utf16Le.encode(b"\xff").is_err());
+    }
+
+    #[test]
+    fn encoded_len() {
+        let text = "h\u{e9}llo \u{1f600}".as_bytes();
+        for encoding in [Encoding::Utf8, Encoding::Utf8Bom, Encoding::Utf16Le] {
+            let encoded = encoding.encode(text).unwrap();
+            assert_eq!(
+                encoded.len(),
+                encoding.bom().len() + encoding.encoded_len(text)
+            );
+        }
+    }
+}
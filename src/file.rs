@@ -0,0 +1,244 @@
+use std::{
+    ffi::OsString,
+    fmt::{Display, Formatter},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    time::SystemTime,
+};
+
+use crate::sys;
+
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    // Use the line ending of the majority of lines
+    pub fn detect(data: &[u8]) -> Self {
+        let lines = data.iter().filter(|c| **c == b'\n').count();
+        let crlf_lines = data.windows(2).filter(|w| w == b"\r\n").count();
+        if crlf_lines * 2 > lines {
+            Self::CrLf
+        } else {
+            Self::Lf
+        }
+    }
+
+    pub const fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::CrLf => b"\r\n",
+        }
+    }
+}
+
+impl Display for LineEnding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        })
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lf" => Ok(Self::Lf),
+            "crlf" => Ok(Self::CrLf),
+            _ => Err(format!("Unknown line ending: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IndentStyle {
+    Tabs,
+    Spaces(usize),
+}
+
+impl IndentStyle {
+    // Guess the style from the leading whitespace of the first lines: tabs if most indented
+    // lines start with a tab, otherwise the most common difference between space indentations
+    pub fn detect<'a>(lines: impl Iterator<Item = &'a [u8]>) -> Option<Self> {
+        let (mut tabs, mut spaces, mut prev) = (0, 0, 0);
+        let mut deltas = [0; 9];
+        for line in lines.take(1000).filter(|l| !l.trim_ascii().is_empty()) {
+            if line.starts_with(b"\t") {
+                tabs += 1;
+                continue;
+            }
+            let n = line.iter().take_while(|c| **c == b' ').count();
+            // Single spaces are usually alignment, as in block comments
+            spaces += usize::from(n > 1);
+            let delta = n.abs_diff(prev);
+            if (2..deltas.len()).contains(&delta) {
+                deltas[delta] += 1;
+            }
+            prev = n;
+        }
+        if tabs > spaces {
+            return Some(Self::Tabs);
+        }
+        let (width, n) = deltas.iter().enumerate().rev().max_by_key(|(_, n)| **n)?;
+        (spaces > 0 && *n > 0).then_some(Self::Spaces(width))
+    }
+}
+
+// Replace the content of a file without truncating it first. The data is written to a
+// temporary file in the same directory, synced, and then renamed over the original, so a
+// crash or a full disk leaves either the old or the new content on disk. A `private` file, like a
+// swap file, is only readable by its owner and replaces a symlink instead of following it.
+// Otherwise the permissions of the original are kept.
+pub fn write_atomic(path: &Path, data: &[u8], private: bool) -> Result<(), String> {
+    let path = match private {
+        true => path.to_path_buf(),
+        false => resolve_links(path),
+    };
+    let file_name = path.file_name().ok_or("Invalid file name")?;
+    // The temporary file is always a new one, so that a file or symlink planted under its name
+    // is never written to
+    let mut attempt = 0;
+    let (mut file, tmp_path) = loop {
+        let mut tmp_name = OsString::from(".");
+        tmp_name.push(file_name);
+        tmp_name.push(format!(".rabi-{}-{attempt}.tmp", process::id()));
+        let tmp_path = path.with_file_name(tmp_name);
+        match sys::create_new(&tmp_path, private) {
+            Ok(file) => break (file, tmp_path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(e) => return Err(format!("{}: {e}", tmp_path.display())),
+        }
+    };
+
+    let result = (|| -> io::Result<()> {
+        file.write_all(data)?;
+        if let Some(meta) = fs::metadata(&path).ok().filter(|_| !private) {
+            // Changing the owner clears the setuid and setgid bits, so it comes first
+            sys::copy_owner(&meta, &tmp_path);
+            fs::set_permissions(&tmp_path, meta.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        // The rename itself is only durable once the folder is synced
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        sys::sync_dir(dir.unwrap_or(Path::new(".")))
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result.map_err(|e| e.to_string())
+}
+
+// Follow symlinks so that the link target gets updated instead of the link, including a link
+// to a file that doesn't exist yet
+fn resolve_links(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    let mut path = path.to_path_buf();
+    // Give up on link loops, like the system does
+    for _ in 0..40 {
+        let Ok(target) = fs::read_link(&path) else {
+            break;
+        };
+        path = path.parent().unwrap_or(Path::new("")).join(target);
+    }
+    path
+}
+
+// Modification time and size, used to notice when another program rewrites a file
+pub fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+// File name standing for unnamed buffers, whose swap file is `.rabi-unnamed.rabi-swp` in the
+// current folder. It is shared by the unnamed buffers of the sessions started there.
+pub const UNNAMED: &str = "rabi-unnamed";
+
+// Swap files live next to the edited file, e.g. `dir/.main.rs.rabi-swp`
+pub fn swap_path(path: &Path) -> PathBuf {
+    let mut swap_name = OsString::from(".");
+    swap_name.push(path.file_name().unwrap_or_default());
+    swap_name.push(".rabi-swp");
+    path.with_file_name(swap_name)
+}
+
+// Returns the swap file of `path` if it was written after the file itself
+pub fn find_newer_swap(path: &Path) -> Option<PathBuf> {
+    let swap = swap_path(path);
+    let swap_time = fs::metadata(&swap).and_then(|m| m.modified()).ok()?;
+    match fs::metadata(path).and_then(|m| m.modified()) {
+        Ok(file_time) if file_time > swap_time => None,
+        _ => Some(swap),
+    }
+}
+
+// Describe which lines differ between two versions of a file
+pub fn diff_summary(old: &[u8], new: &[u8]) -> String {
+    let (old, new): (Vec<_>, Vec<_>) = (
+        old.split(|c| *c == b'\n').collect(),
+        new.split(|c| *c == b'\n').collect(),
+    );
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    if prefix == old.len() && prefix == new.len() {
+        return "No differences".to_string();
+    }
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    format!(
+        "Differs from line {}: {} line(s) on disk, {} line(s) in swap",
+        prefix + 1,
+        old.len() - prefix - suffix,
+        new.len() - prefix - suffix
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(text: &str) -> Option<IndentStyle> {
+        IndentStyle::detect(text.lines().map(str::as_bytes))
+    }
+
+    #[test]
+    fn detect_tabs() {
+        assert_eq!(
+            detect("fn main() {\n\tif x {\n\t\ty();\n\t}\n}\n"),
+            Some(IndentStyle::Tabs)
+        );
+    }
+
+    #[test]
+    fn detect_spaces() {
+        let two = "a:\n  b:\n    c: 1\n  d: 2\n";
+        assert_eq!(detect(two), Some(IndentStyle::Spaces(2)));
+        let four = "def f():\n    if x:\n        return 1\n    return 2\n";
+        assert_eq!(detect(four), Some(IndentStyle::Spaces(4)));
+    }
+
+    #[test]
+    fn detect_ties_pick_the_smaller_width() {
+        assert_eq!(detect("a\n  b\na\n    b\n"), Some(IndentStyle::Spaces(2)));
+    }
+
+    #[test]
+    fn detect_ignores_alignment() {
+        assert_eq!(detect("/*\n * Comment\n */\nint x;\n"), None);
+        assert_eq!(detect("no indentation\n\n"), None);
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt::Write;
+
+use crate::{UTF16BE_BOM, UTF16LE_BOM};
+
+pub const BYTES_PER_ROW: usize = 16;
+
+// Hex dump of a binary file. The cursor is a nibble index, the high nibble of byte `i` being
+// `2 * i`. It may point one byte past the end to append data.
+#[derive(Default, Debug)]
+pub struct HexView {
+    pub bytes: Vec<u8>,
+    pub cursor: usize,
+}
+
+impl HexView {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes, cursor: 0 }
+    }
+
+    // Files with NUL bytes are binary, unless they are UTF-16 text
+    pub fn is_binary(data: &[u8]) -> bool {
+        !data.starts_with(UTF16LE_BOM)
+            && !data.starts_with(UTF16BE_BOM)
+            && data.iter().take(8192).any(|c| *c == 0)
+    }
+
+    pub fn n_rows(&self) -> usize {
+        self.bytes.len() / BYTES_PER_ROW + 1
+    }
+
+    pub fn offset(&self) -> usize {
+        self.cursor / 2
+    }
+
+    pub fn move_cursor(&mut self, delta: isize) {
+        self.cursor = self
+            .cursor
+            .saturating_add_signed(delta)
+            .min(2 * self.bytes.len());
+    }
+
+    pub fn goto(&mut self, offset: usize) {
+        self.cursor = 2 * offset.min(self.bytes.len());
+    }
+
+    // Position of the cursor on screen, as (column, row)
+    pub fn cursor_pos(&self) -> (usize, usize) {
+        let col = self.offset() % BYTES_PER_ROW;
+        (
+            10 + 3 * col + col / 8 + self.cursor % 2,
+            self.offset() / BYTES_PER_ROW,
+        )
+    }
+
+    // Overwrite the nibble under the cursor with `value`, appending a byte at the end
+    pub fn set_nibble(&mut self, value: u8) {
+        let i = self.offset();
+        if i == self.bytes.len() {
+            self.bytes.push(0);
+        }
+        let shift = 4 * (1 - self.cursor % 2);
+        self.bytes[i] = (self.bytes[i] & !(0xf << shift)) | (value << shift);
+        self.cursor += 1;
+    }
+
+    pub fn delete_byte(&mut self, i: usize) -> bool {
+        if i >= self.bytes.len() {
+            return false;
+        }
+        self.bytes.remove(i);
+        self.cursor = 2 * i;
+        true
+    }
+
+    // Offset, hex bytes (split in two groups of 8) and ASCII column, from column `offset` and at
+    // most `max_len` columns wide
+    pub fn draw_row(
+        &self,
+        y: usize,
+        offset: usize,
+        max_len: usize,
+        buffer: &mut String,
+    ) -> Result<(), String> {
+        let start = y * BYTES_PER_ROW;
+        let bytes = &self.bytes[start.min(self.bytes.len())..]
+            [..BYTES_PER_ROW.min(self.bytes.len().saturating_sub(start))];
+        let mut line = String::new();
+        write!(line, "{start:08x}  ").map_err(|e| e.to_string())?;
+        for i in 0..BYTES_PER_ROW {
+            if i == 8 {
+                line.push(' ');
+            }
+            match bytes.get(i) {
+                Some(b) => write!(line, "{b:02x} ").map_err(|e| e.to_string())?,
+                None => line.push_str("   "),
+            }
+        }
+        line.push_str(" |");
+        line.extend(bytes.iter().map(|&b| match b {
+            b' '..=b'~' => b as char,
+            _ => '.',
+        }));
+        line.push('|');
+        buffer.extend(line.chars().skip(offset).take(max_len));
+        Ok(())
+    }
+}
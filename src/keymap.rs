@@ -0,0 +1,215 @@
+use std::path::PathBuf;
+
+use crate::{config_layers, parse_ini};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Key {
+    Arrow(ArrowKey),
+    CtrlArrow(ArrowKey),
+    ShiftArrow(ArrowKey),
+    BackTab,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Delete,
+    Escape,
+    FocusIn,
+    FocusOut,
+    Alt(u8),
+    // F1 to F12
+    Function(u8),
+    Char(u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ArrowKey {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+// Editor actions that can be bound to keys
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Quit,
+    Save,
+    Find,
+    GoTo,
+    Duplicate,
+    Execute,
+    Command,
+    Copy,
+    Cut,
+    Paste,
+    RemoveLine,
+    Refresh,
+}
+
+// Name of each action in `keys.ini`, and its label in the help message, if shown there
+#[rustfmt::skip]
+const ACTIONS: &[(Action, &str, &str)] = &[
+    (Action::Save, "save", "save"),
+    (Action::Quit, "quit", "quit"),
+    (Action::Find, "find", "find"),
+    (Action::GoTo, "goto", "go to"),
+    (Action::Duplicate, "duplicate", "duplicate"),
+    (Action::Execute, "execute", "execute"),
+    (Action::Command, "command", "command"),
+    (Action::Copy, "copy", ""),
+    (Action::Cut, "cut", ""),
+    (Action::Paste, "paste", ""),
+    (Action::RemoveLine, "remove_line", ""),
+    (Action::Refresh, "refresh", ""),
+];
+
+impl Action {
+    // Whether the action is refused in read-only mode
+    pub fn edits(self) -> bool {
+        !matches!(
+            self,
+            Self::Quit | Self::Find | Self::GoTo | Self::Command | Self::Copy | Self::Refresh
+        )
+    }
+}
+
+// Key bindings, in the order they were read
+#[derive(Default, Clone, Debug)]
+pub struct KeyMap(Vec<(Key, Action)>);
+
+impl KeyMap {
+    // Load the embedded `keys.ini`. Each action listed in a user `keys.ini` gets the keys given
+    // there instead, or none if the value is empty.
+    pub fn load(config_dirs: &[PathBuf]) -> Result<Self, Vec<String>> {
+        let mut bindings: Vec<(Key, Action)> = Vec::new();
+        let mut errors = Vec::new();
+        for (location, contents) in config_layers(config_dirs, "keys.ini")? {
+            let result = parse_ini(&location, &contents, &mut |name, value| {
+                let Some(&(action, ..)) = ACTIONS.iter().find(|(_, n, _)| *n == name) else {
+                    return Err(format!("Unknown action: {name}"));
+                };
+                let keys = value
+                    .split(',')
+                    .filter(|s| !s.trim().is_empty())
+                    .map(parse_key)
+                    .collect::<Result<Vec<_>, _>>()?;
+                // A key bound again is taken from its previous action
+                bindings.retain(|(key, a)| *a != action && !keys.contains(key));
+                bindings.extend(keys.into_iter().map(|key| (key, action)));
+                Ok(())
+            });
+            errors.extend(result.err().unwrap_or_default());
+        }
+        if errors.is_empty() {
+            Ok(Self(bindings))
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.0.iter().find(|(k, _)| *k == key).map(|(_, a)| *a)
+    }
+
+    // The first key of each action shown in the help message, like `^S save | ^Q quit`
+    pub fn help(&self) -> String {
+        ACTIONS
+            .iter()
+            .filter(|(.., label)| !label.is_empty())
+            .filter_map(|(action, _, label)| {
+                let (key, _) = self.0.iter().find(|(_, a)| a == action)?;
+                Some(format!("{} {label}", key_name(*key)))
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+}
+
+// Parse `ctrl+KEY`, `alt+KEY` or `F1` to `F12`. Ctrl+H, Ctrl+I, Ctrl+J and Ctrl+M can't be bound
+// since terminals send them for Backspace, Tab and Enter.
+fn parse_key(s: &str) -> Result<Key, String> {
+    let s = s.trim();
+    let key = match s.split_once('+') {
+        Some((modifier, key)) if modifier.eq_ignore_ascii_case("ctrl") => {
+            match key.to_ascii_lowercase().as_bytes() {
+                [c @ b'a'..=b'z'] if !matches!(c, b'h' | b'i' | b'j' | b'm') => {
+                    Some(Key::Char(c & 0x1f))
+                }
+                _ => None,
+            }
+        }
+        Some((modifier, key)) if modifier.eq_ignore_ascii_case("alt") => match key.as_bytes() {
+            [c] if c.is_ascii_graphic() => Some(Key::Alt(*c)),
+            _ => None,
+        },
+        Some(_) => None,
+        None => s
+            .strip_prefix(['f', 'F'])
+            .and_then(|n| n.parse().ok())
+            .filter(|n| (1..=12).contains(n))
+            .map(Key::Function),
+    };
+    key.ok_or_else(|| format!("Invalid key: {s}"))
+}
+
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Char(c) => format!("^{}", (c | 0x40) as char),
+        Key::Alt(c) => format!("Alt+{}", c as char),
+        Key::Function(n) => format!("F{n}"),
+        key => format!("{key:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keys() {
+        assert_eq!(parse_key("ctrl+s"), Ok(Key::Char(0x13)));
+        assert_eq!(parse_key(" Ctrl+S "), Ok(Key::Char(0x13)));
+        assert_eq!(parse_key("alt+x"), Ok(Key::Alt(b'x')));
+        assert_eq!(parse_key("alt+X"), Ok(Key::Alt(b'X')));
+        assert_eq!(parse_key("alt++"), Ok(Key::Alt(b'+')));
+        assert_eq!(parse_key("F1"), Ok(Key::Function(1)));
+        assert_eq!(parse_key("f12"), Ok(Key::Function(12)));
+    }
+
+    #[test]
+    fn reject_invalid_keys() {
+        for key in [
+            "",
+            "s",
+            "ctrl+",
+            "ctrl+1",
+            "ctrl+ab",
+            "ctrl+alt+x",
+            "shift+a",
+            "alt+ ",
+            "alt+é",
+            "F0",
+            "F13",
+            "Fx",
+            "C-M-x",
+            "M-x",
+        ] {
+            assert!(parse_key(key).is_err(), "{key}");
+        }
+    }
+
+    #[test]
+    fn reject_keys_sent_by_other_keys() {
+        for key in ["ctrl+h", "ctrl+i", "ctrl+j", "ctrl+m"] {
+            assert!(parse_key(key).is_err(), "{key}");
+        }
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(key_name(Key::Char(0x13)), "^S");
+        assert_eq!(key_name(Key::Alt(b'x')), "Alt+x");
+        assert_eq!(key_name(Key::Function(10)), "F10");
+    }
+}
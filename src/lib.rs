@@ -1,18 +1,31 @@
+mod cli;
 mod config;
 mod editor;
+mod editorconfig;
+mod encoding;
+mod file;
+mod hex;
+mod keymap;
 mod row;
 mod syntax;
+mod theme;
 
 use std::{
-    fmt::{Display, Formatter},
     io::{self, BufRead, Read, Write},
     str::FromStr,
 };
 
+pub use cli::*;
 pub use config::*;
 pub use editor::*;
+pub use editorconfig::*;
+pub use encoding::*;
+pub use file::*;
+pub use hex::*;
+pub use keymap::*;
 pub use row::*;
 pub use syntax::*;
+pub use theme::*;
 
 #[cfg(windows)]
 mod windows;
@@ -23,9 +36,6 @@ mod unix;
 #[cfg(unix)]
 use unix as sys;
 
-// pub const HELP_MESSAGE: &str = "^S save | ^Q quit | ^F find | ^G go to | ^D duplicate | ^E execute | ^C copy | ^X cut | ^V paste";
-pub const HELP_MESSAGE: &str = "^S save | ^Q quit | ^F find | ^G go to | ^D duplicate | ^E execute";
-
 // ANSI Escape sequences
 pub mod ansi_escape {
     pub const CLEAR_SCREEN: &str = "\x1b[2J"; // Clear from cursor to beginning of the screen
@@ -37,53 +47,190 @@ pub mod ansi_escape {
     pub const CLEAR_LINE_RIGHT_OF_CURSOR: &str = "\x1b[K"; // Clear line right of the current position of the cursor
     pub const DEVICE_STATUS_REPORT: &str = "\x1b[6n"; // Report the cursor position to the application.
     pub const REPOSITION_CURSOR_END: &str = "\x1b[999C\x1b[999B"; // Reposition the cursor to the end of the window
+    pub const ENABLE_FOCUS_REPORTING: &str = "\x1b[?1004h"; // Report focus changes as ESC [ I and ESC [ O
+    pub const DISABLE_FOCUS_REPORTING: &str = "\x1b[?1004l"; // Stop reporting focus changes
 }
 
 pub mod ctrl_key {
     const fn ctrl_key(key: u8) -> u8 {
         key & 0x1f
     }
-    pub const EXIT: u8 = ctrl_key(b'Q');
+    // The other control keys are bound in `keys.ini`
     pub const DELETE_BIS: u8 = ctrl_key(b'H');
-    pub const REFRESH_SCREEN: u8 = ctrl_key(b'L');
-    pub const SAVE: u8 = ctrl_key(b'S');
-    pub const FIND: u8 = ctrl_key(b'F');
-    pub const GOTO: u8 = ctrl_key(b'G');
-    pub const CUT: u8 = ctrl_key(b'X');
-    pub const COPY: u8 = ctrl_key(b'C');
-    pub const PASTE: u8 = ctrl_key(b'V');
-    pub const DUPLICATE: u8 = ctrl_key(b'D');
-    pub const EXECUTE: u8 = ctrl_key(b'E');
-    pub const REMOVE_LINE: u8 = ctrl_key(b'R');
     pub const BACKSPACE: u8 = 127;
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+// A terminal color: one of the 16 basic colors, an entry of the 256-color palette or 24-bit RGB
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
 pub enum Color {
-    Black = 30,
-    Red = 31,
-    Green = 32,
-    Yellow = 33,
-    Blue = 34,
-    Magenta = 35,
-    Cyan = 36,
-    White = 37,
-    Default = 39,
-
-    BlackBG = 40,
-    RedBG = 41,
-    GreenBG = 42,
-    YellowBG = 43,
-    BlueBG = 44,
-    MagentaBG = 45,
-    CyanBG = 46,
-    WhiteBG = 47,
-    DefaultBG = 49,
+    #[default]
+    Default,
+    // The colors of `COLOR_NAMES`, then their bright versions
+    Basic(u8),
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+// Usual RGB values of the basic colors, in xterm
+const BASIC_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+// Levels of each channel in the 6x6x6 color cube of the 256-color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// Colors the terminal can show
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum ColorDepth {
+    None,
+    Basic,
+    Indexed,
+    TrueColor,
 }
 
-impl Display for Color {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "\x1b[{}m", *self as u8)
+impl ColorDepth {
+    // Guess from `NO_COLOR`, `COLORTERM` and `TERM`
+    pub fn detect() -> Self {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        let term = var("TERM");
+        if !var("NO_COLOR").is_empty() || term == "dumb" {
+            Self::None
+        } else if matches!(var("COLORTERM").as_str(), "truecolor" | "24bit") {
+            Self::TrueColor
+        } else if term.contains("256color") {
+            Self::Indexed
+        } else if term.is_empty() && cfg!(windows) {
+            // The Windows console understands 24-bit colors
+            Self::TrueColor
+        } else {
+            Self::Basic
+        }
+    }
+}
+
+// `auto`, `none`, `16`, `256` or `truecolor`
+impl FromStr for ColorDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "auto" => Ok(Self::detect()),
+            "none" => Ok(Self::None),
+            "16" => Ok(Self::Basic),
+            "256" => Ok(Self::Indexed),
+            "truecolor" => Ok(Self::TrueColor),
+            _ => Err(format!(
+                "Invalid colors: {s}, expected auto, none, 16, 256 or truecolor"
+            )),
+        }
+    }
+}
+
+impl Color {
+    // SGR parameters selecting the color as foreground or background
+    pub fn sgr(self, background: bool) -> String {
+        let base = if background { 40 } else { 30 };
+        match self {
+            Self::Default => (base + 9).to_string(),
+            Self::Basic(n) if n < 8 => (base + n as usize).to_string(),
+            Self::Basic(n) => (base + 60 + n as usize - 8).to_string(),
+            Self::Indexed(n) => format!("{};5;{n}", base + 8),
+            Self::Rgb(r, g, b) => format!("{};2;{r};{g};{b}", base + 8),
+        }
+    }
+
+    fn rgb(self) -> Option<(u8, u8, u8)> {
+        match self {
+            Self::Default => None,
+            Self::Basic(n) => Some(BASIC_RGB[n as usize % 16]),
+            Self::Indexed(n @ 0..16) => Some(BASIC_RGB[n as usize]),
+            Self::Indexed(n @ 16..232) => {
+                let n = n as usize - 16;
+                Some((
+                    CUBE_LEVELS[n / 36],
+                    CUBE_LEVELS[n / 6 % 6],
+                    CUBE_LEVELS[n % 6],
+                ))
+            }
+            // Grayscale ramp
+            Self::Indexed(n) => {
+                let level = 8 + 10 * (n - 232);
+                Some((level, level, level))
+            }
+            Self::Rgb(r, g, b) => Some((r, g, b)),
+        }
+    }
+
+    // The closest color that can be shown with `depth`
+    pub fn downgrade(self, depth: ColorDepth) -> Self {
+        let distance = |(r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)| {
+            [(r1, r2), (g1, g2), (b1, b2)]
+                .iter()
+                .map(|&(a, b)| (a as i32 - b as i32).pow(2))
+                .sum::<i32>()
+        };
+        let closest = |rgb, candidates: &mut dyn Iterator<Item = Self>| {
+            candidates.min_by_key(|c| c.rgb().map_or(i32::MAX, |c| distance(rgb, c)))
+        };
+        match (self, depth) {
+            (_, ColorDepth::None) => Self::Default,
+            (Self::Rgb(..), ColorDepth::Indexed) => closest(
+                self.rgb().unwrap_or_default(),
+                &mut (16..=255).map(Self::Indexed),
+            )
+            .unwrap_or(self),
+            (Self::Indexed(n @ 0..16), ColorDepth::Basic) => Self::Basic(n),
+            (Self::Indexed(_) | Self::Rgb(..), ColorDepth::Basic) => closest(
+                self.rgb().unwrap_or_default(),
+                &mut (0..16).map(Self::Basic),
+            )
+            .unwrap_or(self),
+            _ => self,
+        }
+    }
+}
+
+// `default`, a name like `red` or `bright_red`, a palette index from 0 to 255, or `#rrggbb`
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (name, bright) = match s.strip_prefix("bright_") {
+            Some(name) => (name, 8),
+            None => (s, 0),
+        };
+        if let Some(i) = COLOR_NAMES.iter().position(|n| *n == name) {
+            return Ok(Self::Basic(i as u8 + bright));
+        }
+        let invalid = || format!("Invalid color: {s}");
+        match s.strip_prefix('#') {
+            _ if s == "default" => Ok(Self::Default),
+            Some(hex) if hex.len() == 6 && hex.bytes().all(|c| c.is_ascii_hexdigit()) => {
+                let channel = |i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+                Ok(Self::Rgb(channel(0)?, channel(2)?, channel(4)?))
+            }
+            Some(_) => Err(invalid()),
+            None => s.parse().map(Self::Indexed).map_err(|_| invalid()),
+        }
     }
 }
 
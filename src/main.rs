@@ -1,28 +1,47 @@
-use std::path::PathBuf;
+use std::process;
 
-use rabi::{Config, Editor};
+use rabi::{check_config, config_dirs, Cli, Config, Editor};
 
-fn main() -> Result<(), String> {
-    let mut args = std::env::args();
-    let mut config_folder = PathBuf::from(args.next().unwrap());
-    config_folder.pop();
-    config_folder.push("config");
-    // eprintln!("config_folder: {}", config_folder.display());
-    match (args.next(), args.len()) {
-        (Some(arg), 0) if arg == "--help" => {
-            println!(
-                "Rabi - A simple text editor.\n\
-                Usage:\n\
-                rabi        # Create a new file.\n\
-                rabi <file> # Open the specified file.\n\
-                rabi --help # Show this help message.\n"
-            );
+// Errors are printed as they are, e.g. one `file:line: message` per line
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let cli = Cli::parse(std::env::args().skip(1))?;
+    if cli.help {
+        print!("{}", Cli::help());
+        return Ok(());
+    }
+    if cli.version {
+        println!("rabi {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    let mut config_dirs = config_dirs();
+    config_dirs.splice(0..0, cli.config_dir);
+    if cli.check_config {
+        for dir in &config_dirs {
+            println!("Searching {}", dir.display());
+        }
+        let problems = check_config(&config_dirs);
+        for problem in &problems {
+            println!("{problem}");
         }
-        (Some(arg), 0) if arg.starts_with('-') => {
-            return Err(String::from("Arguments error. Run rabi --help for usage."))
+        if !problems.is_empty() {
+            return Err(format!("{} problem(s) found", problems.len()));
         }
-        (file_name, 0) => Editor::new(Config::load(config_folder)?)?.run(file_name)?,
-        _ => return Err(String::from("Arguments error. Run rabi --help for usage.")),
+        println!("No problems found");
+        return Ok(());
+    }
+    let config = Config::load(config_dirs)?;
+    if cli.files.is_empty() {
+        return Editor::new(config)?.run(None, &cli.options);
+    }
+    for (file_name, options) in cli.files {
+        Editor::new(config.clone())?.run(Some(file_name), &options)?;
     }
     Ok(())
 }
@@ -1,17 +1,21 @@
-use std::{fmt::Write, iter::repeat, ops::Range};
+use std::{fmt::Write, iter::repeat_n, ops::Range};
 
+use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthChar;
 
-use crate::{ansi_escape::*, Color, HlState, SyntaxConfig};
+use crate::{ansi_escape::*, HlState, HlType, Style, SyntaxConfig, Theme};
 
+// Positions in `render`, `hl` and `match_range` are render columns
 #[derive(Default, Debug)]
 pub struct Row {
     pub chars: Vec<u8>,
     render: String,
+    // Render column and offset in `render` of each grapheme cluster
+    clusters: Vec<(usize, usize)>,
     // Mapping between chars and render
     pub c2r: Vec<usize>,
     pub r2c: Vec<usize>,
-    hl: Vec<Color>,
+    hl: Vec<HlType>,
     pub hl_state: HlState,
     pub match_range: Option<Range<usize>>,
 }
@@ -25,10 +29,77 @@ impl Row {
         }
     }
 
-    const fn is_sep(c: u8) -> bool {
+    pub const fn is_sep(c: u8) -> bool {
         c.is_ascii_whitespace() || c == b'\0' || (c.is_ascii_punctuation() && c != b'_')
     }
 
+    // Display width of a grapheme cluster: the width of its first character, or 2 for emoji
+    // sequences (flags, emoji with variation selector 16)
+    fn cluster_width(cluster: &str) -> usize {
+        let mut chars = cluster.chars();
+        let first = chars.next().unwrap_or_default();
+        let width = first.width().unwrap_or(1).max(1);
+        let is_flag = ('\u{1f1e6}'..='\u{1f1ff}').contains(&first) && chars.next().is_some();
+        if is_flag || cluster.contains('\u{fe0f}') {
+            width.max(2)
+        } else {
+            width
+        }
+    }
+
+    // Number of grapheme clusters before the byte `cx`
+    pub fn cx2col(&self, cx: usize) -> usize {
+        (0..cx)
+            .filter(|&i| i == 0 || self.c2r[i] != self.c2r[i - 1])
+            .count()
+    }
+
+    // Byte index of the `col`-th grapheme cluster
+    pub fn col2cx(&self, col: usize) -> usize {
+        (0..self.chars.len())
+            .filter(|&i| i == 0 || self.c2r[i] != self.c2r[i - 1])
+            .nth(col)
+            .unwrap_or(self.chars.len())
+    }
+
+    // Leading whitespace of the row
+    pub fn indentation(&self) -> &[u8] {
+        let n = self
+            .chars
+            .iter()
+            .take_while(|c| matches!(c, b' ' | b'\t'))
+            .count();
+        &self.chars[..n]
+    }
+
+    // Width of the row in render columns
+    pub fn width(&self) -> usize {
+        self.c2r.last().copied().unwrap_or_default()
+    }
+
+    // Render columns where the visual lines start when the row is wrapped at `width`, breaking
+    // after whitespace when possible
+    pub fn wrap(&self, width: usize) -> Vec<usize> {
+        let mut starts = vec![0];
+        let (mut line_start, mut last_break) = (0, 0);
+        for (i, &(rx, start)) in self.clusters.iter().enumerate() {
+            let next_rx = self.clusters.get(i + 1).map_or(self.width(), |c| c.0);
+            if next_rx - line_start > width.max(1) && rx > line_start {
+                line_start = if last_break > line_start {
+                    last_break
+                } else {
+                    rx
+                };
+                starts.push(line_start);
+            }
+            if self.render[start..].starts_with(' ') {
+                last_break = next_rx;
+            }
+        }
+        starts
+    }
+
+    // Size in bytes of the grapheme cluster at render position `rx`
     pub fn get_char_size(&self, rx: usize) -> usize {
         self.r2c
             .iter()
@@ -38,44 +109,69 @@ impl Row {
             .unwrap_or(1)
     }
 
-    pub fn update(&mut self, syntax: &SyntaxConfig, mut hl_state: HlState, tab: usize) -> HlState {
+    // Byte index of the grapheme cluster before the one at byte `cx`
+    pub fn prev_cx(&self, cx: usize) -> usize {
+        cx - self.get_char_size(self.c2r[cx] - 1)
+    }
+
+    // Byte index of the grapheme cluster after the one at byte `cx`
+    pub fn next_cx(&self, cx: usize) -> usize {
+        cx + self.get_char_size(self.c2r[cx])
+    }
+
+    pub fn update(&mut self, syntax: &SyntaxConfig, hl_state: HlState, tab: usize) -> HlState {
         self.render.clear();
+        self.clusters.clear();
         self.c2r.clear();
         self.r2c.clear();
         let (mut cx, mut rx) = (0, 0);
-        for c in String::from_utf8_lossy(&self.chars).chars() {
-            let n = if c == '\t' {
-                tab - rx % tab
-            } else {
-                c.width().unwrap_or(1)
-            };
-            self.render.push_str(
-                &(if c == '\t' {
-                    " ".repeat(n)
+        for chunk in self.chars.utf8_chunks() {
+            let valid = chunk.valid().graphemes(true).map(|g| (g, g.len()));
+            // Invalid bytes are shown one by one as replacement characters
+            let invalid = chunk.invalid().iter().map(|_| ("\u{fffd}", 1));
+            for (cluster, len) in valid.chain(invalid) {
+                let n = if cluster == "\t" {
+                    tab - rx % tab
                 } else {
-                    c.to_string()
-                }),
-            );
-            self.c2r.extend(repeat(rx).take(c.len_utf8()));
-            self.r2c.extend(repeat(cx).take(n));
-            cx += c.len_utf8();
-            rx += n;
+                    Self::cluster_width(cluster)
+                };
+                self.clusters.push((rx, self.render.len()));
+                if cluster == "\t" {
+                    self.render.push_str(&" ".repeat(n));
+                } else {
+                    self.render.push_str(cluster);
+                }
+                self.c2r.extend(repeat_n(rx, len));
+                self.r2c.extend(repeat_n(cx, n));
+                cx += len;
+                rx += n;
+            }
         }
         self.c2r.push(rx);
         self.r2c.push(cx);
 
-        self.hl.clear();
-        let line = self.render.as_bytes();
+        let (hl, hl_state) = Self::highlight(&self.chars, syntax, hl_state);
+        self.hl = self.r2c[..rx].iter().map(|&cx| hl[cx]).collect();
+        self.hl_state = match hl_state {
+            HlState::String(_) => HlState::Normal,
+            s => s,
+        };
+        self.hl_state
+    }
 
-        'outer_loop: while self.hl.len() < line.len() {
-            let i = self.hl.len();
-            let find_str = |s: &str| {
-                line.get(i..i + s.len())
-                    .map_or(false, |r| r == s.as_bytes())
-            };
+    // Highlight each byte of `line`
+    fn highlight(
+        line: &[u8],
+        syntax: &SyntaxConfig,
+        mut hl_state: HlState,
+    ) -> (Vec<HlType>, HlState) {
+        let mut hl = Vec::with_capacity(line.len());
+        'outer_loop: while hl.len() < line.len() {
+            let i = hl.len();
+            let find_str = |s: &str| line[i..].starts_with(s.as_bytes());
 
             if hl_state == HlState::Normal && syntax.slcomment_start.iter().any(|s| find_str(s)) {
-                self.hl.extend(repeat(Color::Blue).take(line.len() - i));
+                hl.resize(line.len(), HlType::Comment);
                 continue;
             }
 
@@ -84,25 +180,25 @@ impl Row {
                 (
                     &syntax.mlcomment_delims.as_ref().map(|(a, b)| (a, b)),
                     HlState::MlComment,
-                    Color::Blue,
+                    HlType::Comment,
                 ),
                 (
                     &syntax.mlstring_delims.as_ref().map(|x| (x, x)),
                     HlState::MlString,
-                    Color::Green,
+                    HlType::String,
                 ),
             ] {
                 if let Some((start, end)) = delims {
                     if hl_state == mstate {
                         if find_str(end) {
-                            self.hl.extend(repeat(mtype).take(end.len()));
+                            hl.extend(repeat_n(mtype, end.len()));
                             hl_state = HlState::Normal;
                         } else {
-                            self.hl.push(mtype);
+                            hl.push(mtype);
                         }
                         continue 'outer_loop;
                     } else if hl_state == HlState::Normal && find_str(start) {
-                        self.hl.extend(repeat(mtype).take(start.len()));
+                        hl.extend(repeat_n(mtype, start.len()));
                         hl_state = mstate;
                         continue 'outer_loop;
                     }
@@ -112,83 +208,155 @@ impl Row {
             let c = line[i];
 
             if let HlState::String(quote) = hl_state {
-                self.hl.push(Color::Green);
+                hl.push(HlType::String);
                 if c == quote {
                     hl_state = HlState::Normal;
                 } else if c == b'\\' && i != line.len() - 1 {
-                    self.hl.push(Color::Green);
+                    hl.push(HlType::String);
                 }
                 continue;
             } else if syntax.slstring_quotes.contains(&(c as char)) {
                 hl_state = HlState::String(c);
-                self.hl.push(Color::Green);
+                hl.push(HlType::String);
                 continue;
             }
 
             let prev_sep = i == 0 || Self::is_sep(line[i - 1]);
             if syntax.highlight_numbers
                 && ((c.is_ascii_digit() && prev_sep)
-                    || (i != 0 && self.hl[i - 1] == Color::Red && !prev_sep && !Self::is_sep(c)))
+                    || (i != 0 && hl[i - 1] == HlType::Number && !prev_sep && !Self::is_sep(c)))
             {
-                self.hl.push(Color::Red);
+                hl.push(HlType::Number);
                 continue;
             }
 
             if prev_sep {
-                let s_filter = |s: &str| line.get(i + s.len()).map_or(true, |c| Self::is_sep(*c));
-                for (color, kws) in &syntax.keywords {
-                    for keyword in kws.iter().filter(|kw| find_str(kw) && s_filter(kw)) {
-                        self.hl.extend(repeat(*color).take(keyword.len()));
+                let s_filter = |len| line.get(i + len).is_none_or(|c| Self::is_sep(*c));
+                // The longest keyword starting here and followed by a separator
+                for &len in &syntax.keyword_lengths {
+                    let Some(word) = line.get(i..i + len) else {
+                        continue;
+                    };
+                    let hl_type = if syntax.case_insensitive_keywords {
+                        syntax.keywords.get(&word.to_ascii_lowercase())
+                    } else {
+                        syntax.keywords.get(word)
+                    };
+                    if let Some(hl_type) = hl_type.filter(|_| s_filter(len)) {
+                        hl.extend(repeat_n(*hl_type, len));
+                        continue 'outer_loop;
                     }
                 }
             }
 
-            self.hl.push(Color::Default);
+            hl.push(HlType::Normal);
         }
-
-        if let HlState::String(_) = self.hl_state {
-            self.hl_state = HlState::Normal;
-        }
-        self.hl_state
+        (hl, hl_state)
     }
 
-    pub fn draw(&self, offset: usize, max_len: usize, buffer: &mut String) -> Result<(), String> {
-        let mut current_color = Color::Default;
-        let chars = self.render.chars().skip(offset).take(max_len);
-        let mut rx = self
-            .render
-            .chars()
-            .take(offset)
-            .map(|c| c.width().unwrap_or(1))
-            .sum();
-        for (c, mut color) in chars.zip(self.hl.iter().skip(offset)) {
-            if c.is_ascii_control() {
+    pub fn draw(
+        &self,
+        offset: usize,
+        max_len: usize,
+        selected: bool,
+        theme: &Theme,
+        keyword_styles: &[Style],
+        buffer: &mut String,
+    ) -> Result<(), String> {
+        let mut current_style = None;
+        let end = offset + max_len;
+        for (i, &(rx, start)) in self.clusters.iter().enumerate() {
+            let (next_rx, next_start) = self
+                .clusters
+                .get(i + 1)
+                .copied()
+                .unwrap_or((self.width(), self.render.len()));
+            if next_rx <= offset {
+                continue;
+            } else if rx >= end {
+                break;
+            }
+            let style = if selected {
+                theme.selection
+            } else if self.match_range.as_ref().is_some_and(|r| r.contains(&rx)) {
+                theme.search_match
+            } else {
+                theme.style(self.hl[rx], keyword_styles)
+            };
+            if current_style != Some(style) {
+                buffer.push_str(&style.to_string());
+                current_style = Some(style);
+            }
+            let text = &self.render[start..next_start];
+            if let Some(c) = text.chars().next().filter(char::is_ascii_control) {
                 let c = if (c as u8) < 26 {
                     (b'@' + c as u8) as char
                 } else {
                     '?'
                 };
-                write!(buffer, "{REVERSE_VIDEO}{c}{RESET_FMT}").map_err(|e| e.to_string())?;
-                if current_color != Color::Default {
-                    buffer.push_str(&current_color.to_string());
-                }
+                write!(buffer, "{REVERSE_VIDEO}{c}").map_err(|e| e.to_string())?;
+                // The style is applied again after the reversed character
+                current_style = None::<Style>;
+            } else if rx < offset || next_rx > end {
+                // Wide characters cut by the edge of the screen are replaced by placeholders
+                let placeholder = if rx < offset { "<" } else { ">" };
+                buffer.push_str(&placeholder.repeat(next_rx.min(end) - rx.max(offset)));
             } else {
-                if let Some(range) = &self.match_range {
-                    if range.contains(&rx) {
-                        color = &Color::CyanBG;
-                    } else if rx == range.end {
-                        buffer.push_str(RESET_FMT);
-                    }
-                }
-                if current_color != *color {
-                    buffer.push_str(&color.to_string());
-                    current_color = *color;
-                }
-                buffer.push(c);
+                buffer.push_str(text);
             }
-            rx += c.width().unwrap_or(1);
         }
         buffer.push_str(RESET_FMT);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(text: &str) -> Row {
+        let mut row = Row::new(text.as_bytes().to_vec());
+        row.update(&SyntaxConfig::default(), HlState::Normal, 4);
+        row
+    }
+
+    // Byte indexes reached by moving right from the start, and left from the end
+    fn moves(text: &str) -> (Vec<usize>, Vec<usize>) {
+        let row = row(text);
+        let (mut right, mut left) = (vec![0], vec![text.len()]);
+        while *right.last().unwrap() < text.len() {
+            right.push(row.next_cx(*right.last().unwrap()));
+        }
+        while *left.last().unwrap() > 0 {
+            left.push(row.prev_cx(*left.last().unwrap()));
+        }
+        left.reverse();
+        (right, left)
+    }
+
+    #[test]
+    fn move_by_grapheme_cluster() {
+        for (text, boundaries) in [
+            ("abc", vec![0, 1, 2, 3]),
+            ("a中b", vec![0, 1, 4, 5]),
+            ("ae\u{301}b", vec![0, 1, 4, 5]),
+            ("a\tb", vec![0, 1, 2, 3]),
+            // Woman technologist: woman, ZWJ, laptop
+            ("a\u{1f469}\u{200d}\u{1f4bb}b", vec![0, 1, 12, 13]),
+            // Flag of Japan
+            ("\u{1f1ef}\u{1f1f5}x", vec![0, 8, 9]),
+            ("中", vec![0, 3]),
+        ] {
+            assert_eq!(moves(text), (boundaries.clone(), boundaries), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_bytes_are_single_clusters() {
+        let row = row("a\u{fffd}b");
+        assert_eq!(row.next_cx(1), 4);
+        let mut row = Row::new(b"a\xffb".to_vec());
+        row.update(&SyntaxConfig::default(), HlState::Normal, 4);
+        assert_eq!((row.next_cx(1), row.prev_cx(2)), (2, 1));
+    }
+}
@@ -1,58 +1,260 @@
-use std::{ffi::OsStr, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
 
-use crate::{parse_ini_file, parse_value, parse_values, Color};
+use crate::{config_files, is_syntax_file, parse_ini, parse_value, parse_values, HlType};
 
-#[derive(Default, Debug)]
+#[derive(Default, Clone, Debug)]
 pub struct SyntaxConfig {
     pub name: String,
+    // Name of the file the syntax was read from
+    pub file_name: String,
+    // Files using the syntax: by extension, by exact file name, and by interpreter in the shebang
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    pub shebangs: Vec<String>,
     pub highlight_numbers: bool,
+    pub soft_wrap: Option<bool>,
+    pub text_width: Option<usize>,
+    pub expand_tab: Option<bool>,
+    pub indent_size: Option<usize>,
     pub slcomment_start: Vec<String>,
+    // Line endings after which the next line is indented, and line starts that are dedented
+    pub indent_increase: Vec<String>,
+    pub indent_decrease: Vec<String>,
     pub slstring_quotes: Vec<char>,
     pub mlcomment_delims: Option<(String, String)>,
     pub mlstring_delims: Option<String>,
-    pub keywords: Vec<(Color, Vec<String>)>,
+    // Names of the `keywords_<name>` groups, themed as `keyword_<name>`
+    pub keyword_classes: Vec<String>,
+    // Group of each keyword, and the lengths of the keywords from the longest
+    pub keywords: HashMap<Vec<u8>, HlType>,
+    pub keyword_lengths: Vec<usize>,
+    // Whether keywords match in any case, in which case they are stored in lowercase
+    pub case_insensitive_keywords: bool,
 }
 
 impl SyntaxConfig {
-    pub fn from_ext(ext: &str, config_folder: &Path) -> Result<Option<Self>, String> {
-        let dir_entries = config_folder.read_dir().map_err(|e| e.to_string())?;
-        for dir_entry in dir_entries {
-            let dir_entry = dir_entry.map_err(|e| e.to_string())?;
-            if dir_entry.path().file_name() == Some(OsStr::new("rabi.ini")) {
-                continue;
-            }
-            let (config, extensions) = Self::from_file(&dir_entry.path())?;
-            if extensions.contains(&ext.to_string()) {
-                return Ok(Some(config));
+    // Find a syntax by its name or the name of its file, ignoring case
+    pub fn from_name<'a>(syntaxes: &'a [Self], name: &str) -> Option<&'a Self> {
+        syntaxes.iter().find(|s| s.has_name(name))
+    }
+
+    // Pick the syntax of a file from a modeline, its name, its extension or its shebang, in that
+    // order. `lines` are the first and last lines of the file, starting with the first one.
+    pub fn detect<'a>(syntaxes: &'a [Self], path: &Path, lines: &[String]) -> Option<&'a Self> {
+        let file_name = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+        let ext = path.extension().and_then(OsStr::to_str);
+        let modeline = lines.iter().find_map(|line| modeline_syntax(line));
+        let interpreter = lines.first().and_then(|line| shebang_interpreter(line));
+        let position = |f: &dyn Fn(&Self) -> bool| syntaxes.iter().position(f);
+        let found = modeline
+            .and_then(|name| position(&|s| s.has_name(&name)).or(position(&|s| s.has_ext(&name))))
+            .or_else(|| position(&|s| s.filenames.iter().any(|f| f == file_name)))
+            .or_else(|| ext.and_then(|ext| position(&|s| s.has_ext(ext))))
+            .or_else(|| {
+                let interpreter = interpreter?;
+                // Versions are ignored, e.g. `python3.12` is `python`
+                let unversioned =
+                    interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+                position(&|s| {
+                    s.shebangs
+                        .iter()
+                        .any(|i| i == interpreter || i == unversioned)
+                })
+            });
+        found.map(|i| &syntaxes[i])
+    }
+
+    // Every syntax, and the errors of the broken syntax files, which are skipped
+    pub fn all(config_dirs: &[PathBuf]) -> (Vec<Self>, Vec<String>) {
+        let (files, mut errors) = config_files(config_dirs);
+        let mut syntaxes = Vec::new();
+        for file_name in files.keys().filter(|name| is_syntax_file(name)) {
+            match Self::parse(file_name, &files) {
+                Ok(config) => syntaxes.push(config),
+                Err(e) => errors.extend(e),
             }
         }
-        Ok(None)
+        (syntaxes, errors)
+    }
+
+    fn has_name(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.file_name.strip_suffix(".ini") == Some(&name.to_lowercase())
     }
 
-    pub fn from_file(path: &Path) -> Result<(Self, Vec<String>), String> {
+    fn has_ext(&self, ext: &str) -> bool {
+        self.extensions.iter().any(|e| e == ext)
+    }
+
+    // Parse the syntax file `file_name` of `files`, as returned by `config_files`
+    pub fn parse(
+        file_name: &str,
+        files: &BTreeMap<String, (String, String)>,
+    ) -> Result<Self, Vec<String>> {
+        Self::parse_extending(file_name, files, &mut Vec::new())
+    }
+
+    // `chain` holds the files extended by the one being parsed, to detect cycles
+    fn parse_extending(
+        file_name: &str,
+        files: &BTreeMap<String, (String, String)>,
+        chain: &mut Vec<String>,
+    ) -> Result<Self, Vec<String>> {
+        let (location, contents) = &files[file_name];
+        chain.push(file_name.to_string());
         let mut config = Self::default();
-        let mut extensions = Vec::new();
-        parse_ini_file(path, &mut |key, value| {
+        let mut first_key = true;
+        parse_ini(location, contents, &mut |key, value| {
+            let is_first_key = std::mem::replace(&mut first_key, false);
             match key {
+                // The settings of the extended syntax are the starting point of this one
+                "extends" if !is_first_key => {
+                    return Err("extends must be the first key".to_string())
+                }
+                "extends" => {
+                    let name = value.trim();
+                    let parent = format!("{}.ini", name.to_lowercase());
+                    if !is_syntax_file(&parent) || !files.contains_key(&parent) {
+                        return Err(format!("Unknown syntax to extend: {name}"));
+                    } else if chain.contains(&parent) {
+                        return Err(format!(
+                            "Cyclic extends: {} -> {parent}",
+                            chain.join(" -> ")
+                        ));
+                    }
+                    config = Self::parse_extending(&parent, files, chain)
+                        .map_err(|e| format!("Can't extend {name}: {}", e[0]))?;
+                    // The file names of the extended syntax are its own
+                    config.extensions.clear();
+                    config.filenames.clear();
+                    config.shebangs.clear();
+                }
                 "name" => config.name = parse_value(value)?,
-                "extensions" => extensions.extend(value.split(',').map(|s| s.trim().to_string())),
+                "extensions" => config.extensions = parse_values(value)?,
+                "filenames" => config.filenames = parse_values(value)?,
+                "shebangs" => config.shebangs = parse_values(value)?,
                 "highlight_numbers" => config.highlight_numbers = parse_value(value)?,
+                "soft_wrap" => config.soft_wrap = Some(parse_value(value)?),
+                "text_width" => config.text_width = Some(parse_value(value)?),
+                "expand_tab" => config.expand_tab = Some(parse_value(value)?),
+                "indent_size" => match parse_value(value)? {
+                    0 => return Err("indent_size must be greater than 0".to_string()),
+                    v => config.indent_size = Some(v),
+                },
                 "singleline_comment_start" => config.slcomment_start = parse_values(value)?,
                 "singleline_string_quotes" => config.slstring_quotes = parse_values(value)?,
+                "indent_increase" => config.indent_increase = parse_values(value)?,
+                "indent_decrease" => config.indent_decrease = parse_values(value)?,
                 "multiline_comment_delims" => {
                     config.mlcomment_delims = match &value.split(',').collect::<Vec<_>>()[..] {
                         [v1, v2] => Some((parse_value(v1)?, parse_value(v2)?)),
-                        _ => return Err("mlcomment_delims must have two values".to_string()),
+                        _ => {
+                            return Err("multiline_comment_delims must have two values".to_string())
+                        }
                     }
                 }
-                "multiline_string_delim" => config.mlstring_delims = Some(parse_value(value)?),
-                "keywords_1" => config.keywords.push((Color::Yellow, parse_values(value)?)),
-                "keywords_2" => config.keywords.push((Color::Magenta, parse_values(value)?)),
-                _ => return Err(format!("Unknown key: {}", key)),
+                // An empty value removes the delimiter of an extended syntax
+                "multiline_string_delim" => {
+                    config.mlstring_delims =
+                        Some(parse_value::<String>(value)?).filter(|delim| !delim.is_empty())
+                }
+                "case_insensitive_keywords" => config.set_case_insensitive(parse_value(value)?),
+                // `keywords_<name>+=` adds to the group instead of replacing it
+                _ => match key.strip_prefix("keywords_") {
+                    Some(class) if !class.is_empty() => match class.strip_suffix('+') {
+                        Some(class) => config.add_keywords(class, parse_values(value)?, false),
+                        None => config.add_keywords(class, parse_values(value)?, true),
+                    },
+                    _ => return Err(format!("Unknown key: {key}")),
+                },
             }
             Ok(())
         })?;
-        Ok((config, extensions))
+        chain.pop();
+        config.file_name = file_name.to_string();
+        Ok(config)
+    }
+
+    fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        self.case_insensitive_keywords = case_insensitive;
+        if case_insensitive {
+            let keywords = std::mem::take(&mut self.keywords);
+            for (keyword, hl_type) in keywords {
+                let keyword = keyword.to_ascii_lowercase();
+                self.keywords.entry(keyword).or_insert(hl_type);
+            }
+        }
+    }
+
+    // Add keywords to a group, or replace those of the group. A keyword already in another group
+    // stays there.
+    fn add_keywords(&mut self, class: &str, keywords: Vec<String>, replace: bool) {
+        let i = match self.keyword_classes.iter().position(|c| c == class) {
+            Some(i) => i,
+            None => {
+                self.keyword_classes.push(class.to_string());
+                self.keyword_classes.len() - 1
+            }
+        };
+        if replace {
+            self.keywords
+                .retain(|_, hl_type| *hl_type != HlType::Keyword(i));
+        }
+        for keyword in keywords.into_iter().filter(|k| !k.is_empty()) {
+            let mut keyword = keyword.into_bytes();
+            if self.case_insensitive_keywords {
+                keyword.make_ascii_lowercase();
+            }
+            self.keywords.entry(keyword).or_insert(HlType::Keyword(i));
+        }
+        self.keyword_lengths = self.keywords.keys().map(Vec::len).collect();
+        self.keyword_lengths.sort_unstable_by(|a, b| b.cmp(a));
+        self.keyword_lengths.dedup();
+    }
+}
+
+// The syntax set by a vim modeline, like `vim: set ft=python:`, or an emacs one, like
+// `-*- mode: python -*-` or `-*- python -*-`
+fn modeline_syntax(line: &str) -> Option<String> {
+    let vim = ["vim:", "vi:", "ex:"].iter().find_map(|tag| {
+        let start = line
+            .match_indices(tag)
+            .map(|(i, _)| i)
+            .find(|&i| line[..i].ends_with(char::is_whitespace) || (i == 0 && *tag == "vim:"))?;
+        let options = line[start + tag.len()..].split([' ', '\t', ':']);
+        options
+            .filter_map(|option| option.split_once('='))
+            .find_map(|(key, value)| {
+                matches!(key, "ft" | "filetype" | "syn" | "syntax").then(|| value.to_string())
+            })
+    });
+    vim.or_else(|| {
+        let (_, rest) = line.split_once("-*-")?;
+        let (vars, _) = rest.split_once("-*-")?;
+        let mode = match vars.contains(':') {
+            true => vars.split(';').find_map(|var| {
+                let (key, value) = var.split_once(':')?;
+                key.trim().eq_ignore_ascii_case("mode").then_some(value)
+            })?,
+            false => vars,
+        };
+        Some(mode.trim().to_string()).filter(|mode| !mode.is_empty())
+    })
+}
+
+// The interpreter in a shebang line, like `python3` in `#!/usr/bin/env python3`
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // Options of env, like `-S`, are skipped
+        words.find(|word| !word.starts_with('-') && !word.contains('='))
+    } else {
+        Some(program)
     }
 }
 
@@ -64,3 +266,137 @@ pub enum HlState {
     String(u8),
     MlString,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(files: &[(&str, &str)]) -> BTreeMap<String, (String, String)> {
+        (files.iter())
+            .map(|(name, contents)| (name.to_string(), (name.to_string(), contents.to_string())))
+            .collect()
+    }
+
+    fn keywords(config: &SyntaxConfig, class: &str) -> Vec<String> {
+        let i = config.keyword_classes.iter().position(|c| c == class);
+        let mut keywords: Vec<String> = (config.keywords.iter())
+            .filter(|(_, hl)| Some(**hl) == i.map(HlType::Keyword))
+            .map(|(k, _)| String::from_utf8_lossy(k).to_string())
+            .collect();
+        keywords.sort();
+        keywords
+    }
+
+    #[test]
+    fn extends() {
+        let files = files(&[
+            ("a.ini", "name=A\nextensions=a\nmultiline_string_delim=\"\nkeywords_1=if, else\nkeywords_2=int"),
+            ("b.ini", "extends=a\nname=B\nmultiline_string_delim=\nkeywords_1+=class\nkeywords_2=long"),
+        ]);
+        let b = SyntaxConfig::parse("b.ini", &files).unwrap();
+        assert_eq!(b.name, "B");
+        assert!(b.extensions.is_empty());
+        assert_eq!(b.mlstring_delims, None);
+        assert_eq!(keywords(&b, "1"), ["class", "else", "if"]);
+        assert_eq!(keywords(&b, "2"), ["long"]);
+        let a = SyntaxConfig::parse("a.ini", &files).unwrap();
+        assert_eq!(a.mlstring_delims.as_deref(), Some("\""));
+    }
+
+    #[test]
+    fn modelines() {
+        for (line, syntax) in [
+            ("# vim: set ft=python:", Some("python")),
+            ("// vim:ft=c", Some("c")),
+            ("vim: syntax=rust", Some("rust")),
+            ("/* vi: filetype=cpp */", Some("cpp")),
+            // vi: and ex: need leading whitespace, vim: may start the line
+            ("xvi:ft=c", None),
+            ("vi:ft=c", None),
+            ("index: ft=c", None),
+            ("# vim: ts=4 sw=4", None),
+            ("# -*- mode: ruby -*-", Some("ruby")),
+            ("-*- coding: utf-8; Mode: python -*-", Some("python")),
+            ("// -*- C++ -*-", Some("C++")),
+            ("# -*- coding: utf-8 -*-", None),
+            ("# -*- -*-", None),
+            ("# -*- python", None),
+        ] {
+            assert_eq!(modeline_syntax(line).as_deref(), syntax, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn shebangs() {
+        for (line, interpreter) in [
+            ("#!/bin/sh", Some("sh")),
+            ("#! /usr/bin/python3 -u", Some("python3")),
+            ("#!/usr/bin/env python3", Some("python3")),
+            ("#!/usr/bin/env -S python3.12 -u", Some("python3.12")),
+            ("#!/usr/bin/env VAR=1 node", Some("node")),
+            ("#!/usr/bin/env", None),
+            ("#!", None),
+            ("# /bin/sh", None),
+            ("print('#!/bin/sh')", None),
+        ] {
+            assert_eq!(shebang_interpreter(line), interpreter, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn detect() {
+        let (syntaxes, _) = SyntaxConfig::all(&[]);
+        let detect = |path: &str, lines: &[&str]| {
+            let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+            SyntaxConfig::detect(&syntaxes, Path::new(path), &lines).map(|s| s.name.as_str())
+        };
+        assert_eq!(detect("a.c", &[]), Some("C"));
+        assert_eq!(detect("a.h", &["// -*- C++ -*-"]), Some("C++"));
+        assert_eq!(
+            detect("a.c", &["#!/bin/sh", "# vim: ft=py"]),
+            Some("Python")
+        );
+        assert_eq!(
+            detect("script", &["#!/usr/bin/env -S python3.12 -u"]),
+            Some("Python")
+        );
+        assert_eq!(detect("script.c", &["#!/usr/bin/python3"]), Some("C"));
+        assert_eq!(detect("script", &["#!/usr/bin/unknown"]), None);
+    }
+
+    #[test]
+    fn builtin_cpp() {
+        let (syntaxes, errors) = SyntaxConfig::all(&[]);
+        assert!(errors.is_empty(), "{errors:?}");
+        let delims = |name| {
+            let syntax = syntaxes.iter().find(|s| s.name == name).unwrap();
+            syntax.mlstring_delims.clone()
+        };
+        assert_eq!(delims("C").as_deref(), Some("\""));
+        assert_eq!(delims("C++"), None);
+    }
+
+    #[test]
+    fn extends_errors() {
+        let files = files(&[
+            ("a.ini", "extends=b"),
+            ("b.ini", "extends=a"),
+            ("c.ini", "extends=missing"),
+            ("d.ini", "name=D\nextends=c"),
+            ("e.ini", "extends=c"),
+            ("f.ini", "extends=f"),
+        ]);
+        let error = |name| SyntaxConfig::parse(name, &files).unwrap_err().join("\n");
+        assert_eq!(
+            error("a.ini"),
+            "a.ini:1: Can't extend b: b.ini:1: Cyclic extends: a.ini -> b.ini -> a.ini"
+        );
+        assert_eq!(error("c.ini"), "c.ini:1: Unknown syntax to extend: missing");
+        assert_eq!(error("d.ini"), "d.ini:2: extends must be the first key");
+        assert_eq!(
+            error("e.ini"),
+            "e.ini:1: Can't extend c: c.ini:1: Unknown syntax to extend: missing"
+        );
+        assert_eq!(error("f.ini"), "f.ini:1: Cyclic extends: f.ini -> f.ini");
+    }
+}
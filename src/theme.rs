@@ -0,0 +1,214 @@
+use std::{
+    fmt::{Display, Formatter},
+    fs,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use crate::{parse_ini, Color, ColorDepth};
+
+// The shipped themes, used when no `themes` folder has a file of the same name
+#[rustfmt::skip]
+const THEMES: &[(&str, &str)] = &[
+    ("dark", include_str!("../config/themes/dark.ini")),
+    ("gruvbox-dark", include_str!("../config/themes/gruvbox-dark.ini")),
+    ("light", include_str!("../config/themes/light.ini")),
+    ("solarized-dark", include_str!("../config/themes/solarized-dark.ini")),
+    ("solarized-light", include_str!("../config/themes/solarized-light.ini")),
+];
+
+// Kinds of highlighted text, styled by the theme
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum HlType {
+    #[default]
+    Normal,
+    Number,
+    String,
+    Comment,
+    // Index of the keyword group
+    Keyword(usize),
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    // The style as shown with `depth`. Without colors, a background becomes reverse video.
+    pub fn downgrade(self, depth: ColorDepth) -> Self {
+        Self {
+            fg: self.fg.downgrade(depth),
+            bg: self.bg.downgrade(depth),
+            reverse: self.reverse || (depth == ColorDepth::None && self.bg != Color::Default),
+            ..self
+        }
+    }
+}
+
+// `[bold] [italic] [underline] [reverse] [COLOR] [on COLOR]`
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut style = Self::default();
+        let mut words = s.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "bold" => style.bold = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "reverse" => style.reverse = true,
+                "on" => style.bg = words.next().ok_or("Missing color after on")?.parse()?,
+                color => style.fg = color.parse()?,
+            }
+        }
+        Ok(style)
+    }
+}
+
+// Reset the formatting, then apply the style
+impl Display for Style {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\x1b[0;{};{}", self.fg.sgr(false), self.bg.sgr(true))?;
+        for (enabled, code) in [
+            (self.bold, ";1"),
+            (self.italic, ";3"),
+            (self.underline, ";4"),
+            (self.reverse, ";7"),
+        ] {
+            if enabled {
+                f.write_str(code)?;
+            }
+        }
+        f.write_str("m")
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct Theme {
+    pub normal: Style,
+    pub number: Style,
+    pub string: Style,
+    pub comment: Style,
+    // Styles of the keyword groups by name, reused in turn for the groups without a style
+    pub keywords: Vec<(String, Style)>,
+    pub search_match: Style,
+    pub selection: Style,
+    pub gutter: Style,
+    pub status_bar: Style,
+}
+
+impl Theme {
+    // Load `themes/NAME.ini` from the first configuration folder having it, or the embedded theme
+    pub fn load(name: &str, config_dirs: &[PathBuf]) -> Result<Self, Vec<String>> {
+        let file_name = format!("{name}.ini");
+        let user_file = config_dirs
+            .iter()
+            .map(|dir| dir.join("themes").join(&file_name))
+            .find(|p| p.is_file());
+        match (user_file, THEMES.iter().find(|(n, _)| *n == name)) {
+            (Some(path), _) => {
+                let contents = fs::read_to_string(&path)
+                    .map_err(|e| vec![format!("{}: {e}", path.display())])?;
+                Self::parse(&path.display().to_string(), &contents)
+            }
+            (None, Some((_, contents))) => Self::parse(&format!("built-in theme {name}"), contents),
+            (None, None) => Err(vec![format!("Unknown theme: {name}")]),
+        }
+    }
+
+    pub fn parse(location: &str, contents: &str) -> Result<Self, Vec<String>> {
+        let mut theme = Self::default();
+        parse_ini(location, contents, &mut |key, value| {
+            let style = value.parse()?;
+            match key {
+                "normal" => theme.normal = style,
+                "number" => theme.number = style,
+                "string" => theme.string = style,
+                "comment" => theme.comment = style,
+                "match" => theme.search_match = style,
+                "selection" => theme.selection = style,
+                "gutter" => theme.gutter = style,
+                "status_bar" => theme.status_bar = style,
+                _ => match key.strip_prefix("keyword_") {
+                    Some(class) if !class.is_empty() => {
+                        theme.keywords.retain(|(c, _)| c != class);
+                        theme.keywords.push((class.to_string(), style));
+                    }
+                    _ => return Err(format!("Unknown key: {key}")),
+                },
+            }
+            Ok(())
+        })?;
+        Ok(theme)
+    }
+
+    // Adapt the colors to what the terminal can show
+    pub fn downgrade(&mut self, depth: ColorDepth) {
+        let styles = [
+            &mut self.normal,
+            &mut self.number,
+            &mut self.string,
+            &mut self.comment,
+            &mut self.search_match,
+            &mut self.selection,
+            &mut self.gutter,
+            &mut self.status_bar,
+        ];
+        let keywords = self.keywords.iter_mut().map(|(_, style)| style);
+        for style in styles.into_iter().chain(keywords) {
+            *style = style.downgrade(depth);
+        }
+    }
+
+    // Style of each keyword group of a syntax
+    pub fn keyword_styles(&self, classes: &[String]) -> Vec<Style> {
+        let styles = classes.iter().enumerate().map(|(i, class)| {
+            match self.keywords.iter().find(|(c, _)| c == class) {
+                Some((_, style)) => *style,
+                None if self.keywords.is_empty() => self.normal,
+                None => self.keywords[i % self.keywords.len()].1,
+            }
+        });
+        styles.collect()
+    }
+
+    pub fn style(&self, hl: HlType, keyword_styles: &[Style]) -> Style {
+        match hl {
+            HlType::Normal => self.normal,
+            HlType::Number => self.number,
+            HlType::String => self.string,
+            HlType::Comment => self.comment,
+            HlType::Keyword(i) => keyword_styles.get(i).copied().unwrap_or(self.normal),
+        }
+    }
+}
+
+// Problems of the theme files of the configuration folders
+pub fn check_themes(config_dirs: &[PathBuf]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for dir in config_dirs {
+        let Ok(dir_entries) = dir.join("themes").read_dir() else {
+            continue;
+        };
+        for path in dir_entries.filter_map(|entry| Some(entry.ok()?.path())) {
+            if path.extension().is_none_or(|ext| ext != "ini") || !path.is_file() {
+                continue;
+            }
+            let location = path.display().to_string();
+            match fs::read_to_string(&path) {
+                Ok(contents) => {
+                    problems.extend(Theme::parse(&location, &contents).err().unwrap_or_default())
+                }
+                Err(e) => problems.push(format!("{location}: {e}")),
+            }
+        }
+    }
+    problems
+}
@@ -1,7 +1,33 @@
 // TODO: unix support
 
+use std::{
+    fs::{File, Metadata, OpenOptions},
+    io,
+    os::unix::fs::{chown, MetadataExt, OpenOptionsExt},
+    path::Path,
+};
+
 pub use libc::termios as TerminalMode;
 
 pub fn monitor_winsize() -> Result<(), String> {
     todo!()
 }
+
+// Best effort: only root may give a file away to another user
+pub fn copy_owner(meta: &Metadata, path: &Path) {
+    let _ = chown(path, Some(meta.uid()), Some(meta.gid()));
+}
+
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+// Create a file that doesn't exist yet, not even as a symlink. A private file is only readable
+// and writable by its owner.
+pub fn create_new(path: &Path, private: bool) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(if private { 0o600 } else { 0o666 })
+        .open(path)
+}
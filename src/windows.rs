@@ -1,3 +1,9 @@
+use std::{
+    fs::{Metadata, OpenOptions},
+    io,
+    path::Path,
+};
+
 use winapi::um::wincon::*;
 use winapi_util::{console, HandleRef};
 
@@ -24,6 +30,17 @@ pub const fn winsize_changed() -> bool {
     false
 }
 
+pub const fn copy_owner(_: &Metadata, _: &Path) {}
+
+// The standard library can't open folders on Windows to sync them
+pub const fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+pub fn create_new(path: &Path, _: bool) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
 pub fn set_terminal_mode((stdin_mode, stdout_mode): TerminalMode) -> Result<(), String> {
     console::set_mode(HandleRef::stdin(), stdin_mode).map_err(|e| e.to_string())?;
     console::set_mode(HandleRef::stdout(), stdout_mode).map_err(|e| e.to_string())